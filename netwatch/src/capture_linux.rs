@@ -0,0 +1,244 @@
+use pnet::datalink::{DataLinkReceiver, NetworkInterface};
+
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::time::Duration;
+
+use crate::filter::{Filter, Instruction, DROP_ALL};
+
+const ETH_P_ALL: u16 = 0x0003;
+const ETH_P_8021Q: u16 = 0x8100;
+
+// See linux/if_packet.h, which libc doesn't have all of.
+const PACKET_AUXDATA: libc::c_int = 8;
+const TP_STATUS_VLAN_VALID: u32 = 0x10;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 0x40;
+
+// Where an 802.1Q tag goes in an Ethernet frame (after the addresses), and how long it is.
+const VLAN_TAG_OFFSET: usize = 12;
+const VLAN_TAG_LEN: usize = 4;
+
+const BUFFER_LEN: usize = 65_536;
+
+// `struct tpacket_auxdata`, which comes with each frame once `PACKET_AUXDATA` is enabled.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct AuxData {
+  tp_status: u32,
+  tp_len: u32,
+  tp_snaplen: u32,
+  tp_mac: u16,
+  tp_net: u16,
+  tp_vlan_tci: u16,
+  tp_vlan_tpid: u16,
+}
+
+// An `AF_PACKET` socket, used in place of pnet's channel on Ethernet links since pnet doesn't
+// give us access to its socket. That lets us attach a BPF program, and put back the 802.1Q tags
+// the kernel takes out of frames (into their metadata) before we get them, like libpcap does.
+pub struct PacketReceiver {
+  fd: RawFd,
+  buffer: Vec<u8>,
+  timeout: Duration,
+}
+
+impl PacketReceiver {
+  /// Opens a promiscuous socket on `interface`, which only receives frames matching `filter`.
+  pub fn open(
+    interface: &NetworkInterface,
+    filter: Option<&Filter>,
+    timeout: Duration,
+  ) -> io::Result<PacketReceiver> {
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, ETH_P_ALL.to_be() as i32) };
+    if fd == -1 {
+      return Err(io::Error::last_os_error());
+    }
+
+    // Owning the fd straight away means it's closed on any of the early returns below. There's
+    // room after the frame for the tag we may put back.
+    let receiver = PacketReceiver {
+      fd,
+      buffer: vec![0; BUFFER_LEN + VLAN_TAG_LEN],
+      timeout,
+    };
+
+    // Until the socket is bound it receives from every interface, so drop everything until then,
+    // and throw away anything that was queued before swapping in the real filter.
+    receiver.attach(DROP_ALL)?;
+    receiver.bind(interface)?;
+    receiver.set_option(libc::SOL_PACKET, PACKET_AUXDATA, &1 as &libc::c_int)?;
+    receiver.set_option(
+      libc::SOL_PACKET,
+      libc::PACKET_ADD_MEMBERSHIP,
+      &libc::packet_mreq {
+        mr_ifindex: interface.index as i32,
+        mr_type: libc::PACKET_MR_PROMISC as u16,
+        mr_alen: 0,
+        mr_address: [0; 8],
+      },
+    )?;
+    receiver.drain();
+    match filter {
+      Some(filter) => receiver.attach(filter.program())?,
+      None => receiver.detach()?,
+    }
+
+    Ok(receiver)
+  }
+
+  fn set_option<T>(&self, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+    let result = unsafe {
+      libc::setsockopt(
+        self.fd,
+        level,
+        name,
+        value as *const T as *const libc::c_void,
+        mem::size_of::<T>() as libc::socklen_t,
+      )
+    };
+
+    if result == -1 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+  }
+
+  fn attach(&self, program: &[Instruction]) -> io::Result<()> {
+    let program = libc::sock_fprog {
+      len: program.len() as u16,
+      // `Instruction` has the same layout as `sock_filter`.
+      filter: program.as_ptr() as *mut libc::sock_filter,
+    };
+
+    self.set_option(libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &program)
+  }
+
+  fn detach(&self) -> io::Result<()> {
+    self.set_option(libc::SOL_SOCKET, libc::SO_DETACH_FILTER, &0 as &libc::c_int)
+  }
+
+  fn bind(&self, interface: &NetworkInterface) -> io::Result<()> {
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = ETH_P_ALL.to_be();
+    addr.sll_ifindex = interface.index as i32;
+
+    let result = unsafe {
+      libc::bind(
+        self.fd,
+        &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+        mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+      )
+    };
+
+    if result == -1 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+  }
+
+  fn drain(&self) {
+    let mut buffer = [0u8; 1];
+    while unsafe {
+      libc::recv(
+        self.fd,
+        buffer.as_mut_ptr() as *mut libc::c_void,
+        buffer.len(),
+        libc::MSG_DONTWAIT,
+      )
+    } >= 0
+    {}
+  }
+}
+
+impl DataLinkReceiver for PacketReceiver {
+  fn next(&mut self) -> io::Result<&[u8]> {
+    let mut pollfd = libc::pollfd {
+      fd: self.fd,
+      events: libc::POLLIN,
+      revents: 0,
+    };
+
+    match unsafe { libc::poll(&mut pollfd, 1, self.timeout.as_millis() as libc::c_int) } {
+      -1 => {
+        let e = io::Error::last_os_error();
+        return match e.kind() {
+          // Treat signals like a timeout, so the caller just tries again.
+          io::ErrorKind::Interrupted => Err(io::Error::new(io::ErrorKind::TimedOut, e)),
+          _ => Err(e),
+        };
+      }
+      0 => return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+      _ => {}
+    }
+
+    let mut iov = libc::iovec {
+      iov_base: self.buffer.as_mut_ptr() as *mut libc::c_void,
+      iov_len: BUFFER_LEN,
+    };
+    // Room for the `tpacket_auxdata` control message, aligned like a `cmsghdr`.
+    let mut control = [0u64; 8];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(self.fd, &mut message, 0) };
+    if len == -1 {
+      return Err(io::Error::last_os_error());
+    }
+
+    let len = len as usize;
+    match vlan_tag(&message) {
+      Some((tpid, tci)) if len >= VLAN_TAG_OFFSET => {
+        self
+          .buffer
+          .copy_within(VLAN_TAG_OFFSET..len, VLAN_TAG_OFFSET + VLAN_TAG_LEN);
+        self.buffer[VLAN_TAG_OFFSET..VLAN_TAG_OFFSET + 2].copy_from_slice(&tpid.to_be_bytes());
+        self.buffer[VLAN_TAG_OFFSET + 2..VLAN_TAG_OFFSET + 4].copy_from_slice(&tci.to_be_bytes());
+        Ok(&self.buffer[..len + VLAN_TAG_LEN])
+      }
+      _ => Ok(&self.buffer[..len]),
+    }
+  }
+}
+
+// The TPID and TCI of the 802.1Q tag the kernel took out of a frame, from its `tpacket_auxdata`.
+// A TCI of zero is only a tag if the kernel says so (a frame can be tagged with just a priority).
+fn vlan_tag(message: &libc::msghdr) -> Option<(u16, u16)> {
+  let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(message) };
+  while !cmsg.is_null() {
+    let header = unsafe { &*cmsg };
+    if header.cmsg_level == libc::SOL_PACKET && header.cmsg_type == PACKET_AUXDATA {
+      let aux = unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const AuxData) };
+      if aux.tp_vlan_tci == 0 && aux.tp_status & TP_STATUS_VLAN_VALID == 0 {
+        return None;
+      }
+
+      let tpid = if aux.tp_status & TP_STATUS_VLAN_TPID_VALID != 0 {
+        aux.tp_vlan_tpid
+      } else {
+        ETH_P_8021Q
+      };
+      return Some((tpid, aux.tp_vlan_tci));
+    }
+
+    cmsg = unsafe { libc::CMSG_NXTHDR(message, cmsg) };
+  }
+
+  None
+}
+
+impl Drop for PacketReceiver {
+  fn drop(&mut self) {
+    unsafe {
+      libc::close(self.fd);
+    }
+  }
+}
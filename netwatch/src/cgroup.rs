@@ -0,0 +1,203 @@
+use std::fs;
+
+use crate::error::{Error, Result};
+use crate::port::PID;
+
+// Length of the IDs Docker, Podman and containerd give containers, and of the short form shown
+// by `docker ps`.
+const CONTAINER_ID_LEN: usize = 64;
+const SHORT_CONTAINER_ID_LEN: usize = 12;
+
+// Cgroup is the control group a process is in, e.g. `/system.slice/nginx.service`. systemd puts
+// every service in its own, and so do container runtimes for every container, so it says what a
+// process belongs to better than its name or its parent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cgroup {
+  path: String,
+}
+
+impl Cgroup {
+  /// Reads `/proc/{PID}/cgroup`, preferring the unified (v2) hierarchy over the systemd one.
+  pub fn read(pid: PID) -> Result<Cgroup> {
+    let path = format!("/proc/{}/cgroup", pid);
+    let contents = fs::read_to_string(&path)?;
+    Cgroup::parse(&contents).ok_or_else(|| Error::Parse(format!("no control group in {}", path)))
+  }
+
+  // Each line is `HIERARCHY-ID:CONTROLLERS:PATH`, the unified hierarchy has ID 0 and no
+  // controllers.
+  fn parse(contents: &str) -> Option<Cgroup> {
+    let lines = contents
+      .lines()
+      .filter_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        Some((fields.next()?, fields.next()?, fields.next()?))
+      })
+      .collect::<Vec<_>>();
+
+    lines
+      .iter()
+      .find(|(id, controllers, _)| *id == "0" && controllers.is_empty())
+      .or_else(|| {
+        lines
+          .iter()
+          .find(|(_, controllers, _)| *controllers == "name=systemd")
+      })
+      .or_else(|| lines.first())
+      .map(|(_, _, path)| Cgroup {
+        path: path.to_string(),
+      })
+  }
+
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+
+  /// The systemd service or scope the process is in (e.g., `nginx.service`), or failing that its
+  /// slice (e.g., `user-1000.slice`).
+  pub fn unit(&self) -> Option<&str> {
+    let components = self.components().collect::<Vec<&str>>();
+    components
+      .iter()
+      .rev()
+      .find(|component| component.ends_with(".service") || component.ends_with(".scope"))
+      .or_else(|| {
+        components
+          .iter()
+          .rev()
+          .find(|component| component.ends_with(".slice"))
+      })
+      .copied()
+  }
+
+  /// The ID of the container the process is in, shortened like `docker ps` does. Recognizes
+  /// Docker, Podman, containerd and CRI-O (with or without systemd, so including Kubernetes) and
+  /// LXC, whose containers have names rather than IDs.
+  pub fn container(&self) -> Option<&str> {
+    let mut components = self.components().peekable();
+    while let Some(component) = components.next() {
+      // `docker-{ID}.scope`, `libpod-{ID}.scope`, `cri-containerd-{ID}.scope`, ..., or just the
+      // ID when the runtime manages cgroups itself (e.g., `/docker/{ID}`).
+      let id = component.trim_end_matches(".scope");
+      let id = id.rsplit('-').next().unwrap_or(id);
+      if id.len() == CONTAINER_ID_LEN && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(&id[..SHORT_CONTAINER_ID_LEN]);
+      }
+
+      if component.starts_with("lxc.payload.") {
+        return Some(component.trim_start_matches("lxc.payload."));
+      }
+      if component == "lxc" {
+        if let Some(name) = components.peek() {
+          return Some(name);
+        }
+      }
+    }
+
+    None
+  }
+
+  fn components(&self) -> impl Iterator<Item = &str> {
+    self
+      .path
+      .split('/')
+      .filter(|component| !component.is_empty())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ID: &str = "3f4a9c2b1d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a";
+
+  fn cgroup(path: &str) -> Cgroup {
+    Cgroup { path: path.into() }
+  }
+
+  #[test]
+  fn prefers_the_unified_hierarchy() {
+    let hybrid = "12:pids:/system.slice/nginx.service\n\
+                  1:name=systemd:/system.slice/nginx.service\n\
+                  0::/system.slice/nginx.service\n";
+    assert_eq!(
+      Cgroup::parse(hybrid).unwrap().path(),
+      "/system.slice/nginx.service"
+    );
+
+    let unified = "0::/user.slice/user-1000.slice/session-2.scope\n";
+    assert_eq!(
+      Cgroup::parse(unified).unwrap().path(),
+      "/user.slice/user-1000.slice/session-2.scope"
+    );
+  }
+
+  #[test]
+  fn falls_back_to_the_systemd_hierarchy() {
+    let legacy = "4:memory:/docker\n2:name=systemd:/system.slice/docker.service\n";
+    assert_eq!(
+      Cgroup::parse(legacy).unwrap().path(),
+      "/system.slice/docker.service"
+    );
+
+    let other = "4:memory:/lxc/web\n3:cpu:/lxc/web\n";
+    assert_eq!(Cgroup::parse(other).unwrap().path(), "/lxc/web");
+  }
+
+  #[test]
+  fn paths_can_contain_colons() {
+    assert_eq!(Cgroup::parse("0::/a:b\n").unwrap().path(), "/a:b");
+  }
+
+  #[test]
+  fn nothing_to_parse() {
+    assert_eq!(Cgroup::parse(""), None);
+    assert_eq!(Cgroup::parse("garbage\n"), None);
+  }
+
+  #[test]
+  fn units() {
+    assert_eq!(
+      cgroup("/system.slice/nginx.service").unit(),
+      Some("nginx.service")
+    );
+    assert_eq!(
+      cgroup("/user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox.scope").unit(),
+      Some("app-firefox.scope")
+    );
+    assert_eq!(
+      cgroup("/user.slice/user-1000.slice").unit(),
+      Some("user-1000.slice")
+    );
+    assert_eq!(cgroup("/").unit(), None);
+  }
+
+  #[test]
+  fn containers() {
+    let short = &ID[..SHORT_CONTAINER_ID_LEN];
+    for path in [
+      format!("/system.slice/docker-{}.scope", ID),
+      format!("/docker/{}", ID),
+      format!("/machine.slice/libpod-{}.scope/container", ID),
+      format!(
+        "/kubepods.slice/kubepods-pod1.slice/cri-containerd-{}.scope",
+        ID
+      ),
+      format!("/kubepods.slice/kubepods-pod1.slice/crio-{}.scope", ID),
+      format!("/kubepods/besteffort/pod1/{}", ID),
+    ]
+    .iter()
+    {
+      assert_eq!(cgroup(path).container(), Some(short), "{}", path);
+    }
+
+    assert_eq!(
+      cgroup("/lxc.payload.web/init.scope").container(),
+      Some("web")
+    );
+    assert_eq!(cgroup("/lxc/web").container(), Some("web"));
+    assert_eq!(cgroup("/system.slice/nginx.service").container(), None);
+    // Too short to be a container's ID.
+    assert_eq!(cgroup("/docker/3f4a9c2b1d8e").container(), None);
+  }
+}
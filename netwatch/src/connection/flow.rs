@@ -0,0 +1,118 @@
+use pnet::datalink::NetworkInterface;
+
+use std::fmt::{Display, Formatter, Error};
+use std::net::{IpAddr, SocketAddr};
+use std::result::Result;
+
+use crate::incoming::IsIncoming;
+use crate::packet_monitor::SrcDest;
+use crate::port::Port;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Protocol {
+  Tcp,
+  Udp,
+  // ICMP echo requests and replies, whose identifier is used as the port (like ping sockets do).
+  Icmp,
+  Icmpv6,
+  // Raw sockets, which use the IP protocol they're for as their port. Only sockets have this.
+  Raw,
+}
+
+impl Display for Protocol {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.pad(match self {
+      Protocol::Tcp => "TCP",
+      Protocol::Udp => "UDP",
+      Protocol::Icmp => "ICMP",
+      Protocol::Icmpv6 => "ICMPv6",
+      Protocol::Raw => "RAW",
+    })
+  }
+}
+
+// A Flow identifies a single conversation by its 5-tuple, always expressed from our side:
+// `local_*` is the endpoint on this machine and `remote_*` is the peer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Flow {
+  pub protocol: Protocol,
+  pub local_addr: IpAddr,
+  pub local_port: Port,
+  pub remote_addr: IpAddr,
+  pub remote_port: Port,
+}
+
+impl Flow {
+  /// Builds a `Flow` from a packet's addresses and ports, working out which side is local.
+  /// Also returns whether the packet was incoming, so its bytes can be attributed correctly.
+  pub fn new(
+    interface: &NetworkInterface,
+    protocol: Protocol,
+    src_dest: &SrcDest,
+    source_port: Port,
+    destination_port: Port,
+  ) -> (Flow, bool) {
+    let is_incoming = src_dest.1.is_incoming(interface);
+    let flow = Flow::directed(
+      protocol,
+      src_dest,
+      source_port,
+      destination_port,
+      is_incoming,
+    );
+
+    (flow, is_incoming)
+  }
+
+  /// Like `new`, but for when it's already known which way the packet was going (e.g., inside a
+  /// tunnel, where the addresses aren't the interface's).
+  pub fn directed(
+    protocol: Protocol,
+    src_dest: &SrcDest,
+    source_port: Port,
+    destination_port: Port,
+    is_incoming: bool,
+  ) -> Flow {
+    let (source, destination) = (src_dest.0, src_dest.1);
+    if is_incoming {
+      Flow {
+        protocol,
+        local_addr: destination,
+        local_port: destination_port,
+        remote_addr: source,
+        remote_port: source_port,
+      }
+    } else {
+      Flow {
+        protocol,
+        local_addr: source,
+        local_port: source_port,
+        remote_addr: destination,
+        remote_port: destination_port,
+      }
+    }
+  }
+
+  /// Builds a `Flow` for an ICMP or ICMPv6 echo request or reply going the way `is_incoming` says.
+  /// Echoes don't have ports, but ping sockets are bound to their identifier as if it were one, so
+  /// it's used as the local port.
+  pub fn echo(protocol: Protocol, src_dest: &SrcDest, identifier: u16, is_incoming: bool) -> Flow {
+    let mut flow = Flow::directed(protocol, src_dest, identifier, identifier, is_incoming);
+    flow.remote_port = 0;
+    flow
+  }
+
+  pub fn local(&self) -> SocketAddr {
+    SocketAddr::new(self.local_addr, self.local_port)
+  }
+
+  pub fn remote(&self) -> SocketAddr {
+    SocketAddr::new(self.remote_addr, self.remote_port)
+  }
+}
+
+impl Display for Flow {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.pad(&format!("{} {} <-> {}", self.protocol, self.local(), self.remote()))
+  }
+}
@@ -0,0 +1,214 @@
+use procfs::process::Process;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{Display, Error, Formatter};
+use std::result::Result;
+
+use crate::cgroup::Cgroup;
+use crate::connection::Flow;
+use crate::port::{PortMapper, PID};
+use crate::transfer::Transfer;
+use crate::user::Users;
+
+/// What traffic is attributed to: each process, or groups of them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Grouping {
+  Process,
+  /// The systemd unit (or cgroup, for processes outside of one).
+  Unit,
+  Container,
+  User,
+}
+
+impl Grouping {
+  pub const ALL: [Grouping; 4] = [
+    Grouping::Process,
+    Grouping::Unit,
+    Grouping::Container,
+    Grouping::User,
+  ];
+
+  /// The group a process is in, or `None` when grouping by process. Processes outside of
+  /// containers all go in one group, and users are the ones processes run as (`GroupList` prefers
+  /// whoever created the socket).
+  pub fn group(self, membership: &Membership) -> Option<String> {
+    let cgroup = membership.cgroup.as_ref();
+    match self {
+      Grouping::Process => None,
+      Grouping::Unit => Some(cgroup.map_or("<unknown>".into(), |cgroup| {
+        cgroup.unit().unwrap_or_else(|| cgroup.path()).to_string()
+      })),
+      Grouping::Container => Some(
+        cgroup
+          .and_then(Cgroup::container)
+          .unwrap_or("<host>")
+          .to_string(),
+      ),
+      Grouping::User => Some(membership.user.clone()),
+    }
+  }
+}
+
+/// What a process belongs to.
+#[derive(Debug, Clone)]
+pub struct Membership {
+  pub cgroup: Option<Cgroup>,
+  /// The user the process runs as.
+  pub user: String,
+}
+
+// Memberships looks up what each process belongs to once, when it first shows up, since that
+// doesn't change while it runs.
+#[derive(Debug, Clone, Default)]
+pub struct Memberships {
+  users: Users,
+  memberships: HashMap<PID, Membership>,
+}
+
+impl Memberships {
+  /// Names users with `users`.
+  pub fn new(users: Users) -> Memberships {
+    Memberships {
+      users,
+      memberships: HashMap::new(),
+    }
+  }
+
+  pub fn get(&mut self, process: &Process) -> &Membership {
+    let users = &self.users;
+    self
+      .memberships
+      .entry(process.pid)
+      .or_insert_with(|| Membership {
+        cgroup: Cgroup::read(process.pid).ok(),
+        user: users.display(process.owner),
+      })
+  }
+
+  /// Forgets the processes `keep` returns false for, e.g., because they've exited.
+  pub fn retain<F: FnMut(PID) -> bool>(&mut self, mut keep: F) {
+    self.memberships.retain(|pid, _| keep(*pid));
+  }
+
+  pub fn values(&self) -> impl Iterator<Item = &Membership> {
+    self.memberships.values()
+  }
+
+  pub fn users(&self) -> &Users {
+    &self.users
+  }
+}
+
+// GroupList is what a `ConnectionTable` transferred per group of processes, like
+// `ConnectionList` is per process, which is what to use when grouping by process.
+#[derive(Debug, Clone)]
+pub struct GroupList {
+  grouping: Grouping,
+  groups: HashMap<String, Transfer>,
+  /// A `Transfer` for flows without a process.
+  unknown: Transfer,
+  /// Everything inserted, counting a flow shared by several groups once.
+  total: Transfer,
+}
+
+impl GroupList {
+  pub fn new(grouping: Grouping) -> GroupList {
+    GroupList {
+      grouping,
+      groups: HashMap::new(),
+      unknown: Transfer::new(),
+      total: Transfer::new(),
+    }
+  }
+
+  /// Attributes `transfer` to the groups of each process with a socket `flow` matches, or to
+  /// unknown if there are none. Processes often share sockets (e.g., workers accepting on the
+  /// same listener), so a group only counts a flow once however many of its processes have it.
+  ///
+  /// Users are whoever created the socket, which is who the traffic is for even if the socket
+  /// was handed to a process running as someone else (e.g., by a setuid helper), falling back to
+  /// who the processes run as if that isn't known.
+  pub fn insert_flow(
+    &mut self,
+    flow: &Flow,
+    transfer: &Transfer,
+    port_mapper: &PortMapper,
+    memberships: &mut Memberships,
+  ) {
+    let processes = match port_mapper.get(flow.protocol, flow.local_addr, flow.local_port) {
+      Some(processes) => processes,
+      None => return self.insert_unknown(transfer),
+    };
+
+    let uid = match self.grouping {
+      Grouping::User => port_mapper.uid(flow.protocol, flow.local_addr, flow.local_port),
+      _ => None,
+    };
+    let mut groups = vec![];
+    match uid {
+      Some(uid) => groups.push(memberships.users().display(uid)),
+      None => {
+        for process in processes {
+          if let Some(group) = self.grouping.group(memberships.get(process)) {
+            if !groups.contains(&group) {
+              groups.push(group);
+            }
+          }
+        }
+      }
+    }
+    for group in groups {
+      self.groups.entry(group).or_default().merge(transfer);
+    }
+    self.total.merge(transfer);
+  }
+
+  pub fn insert_unknown(&mut self, transfer: &Transfer) {
+    self.unknown.merge(transfer);
+    self.total.merge(transfer);
+  }
+
+  pub fn grouping(&self) -> Grouping {
+    self.grouping
+  }
+
+  pub fn get(&self, group: &str) -> Option<&Transfer> {
+    self.groups.get(group)
+  }
+
+  pub fn unknown(&self) -> &Transfer {
+    &self.unknown
+  }
+
+  pub fn total(&self) -> &Transfer {
+    &self.total
+  }
+
+  pub fn len(&self) -> usize {
+    self.groups.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.groups.is_empty()
+  }
+
+  /// Groups with the most traffic first, and by name when they're tied.
+  pub fn iter(&self) -> impl Iterator<Item = (&String, &Transfer)> {
+    let mut groups = self.groups.iter().collect::<Vec<_>>();
+    groups.sort_by_key(|(group, transfer)| {
+      (Reverse(transfer.incoming() + transfer.outgoing()), *group)
+    });
+    groups.into_iter()
+  }
+}
+
+impl Display for GroupList {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    for (group, transfer) in self.iter() {
+      writeln!(f, "{} {}", transfer, group)?;
+    }
+    writeln!(f, "Unknown: {}", self.unknown)?;
+    write!(f, "Total:   {}", self.total)
+  }
+}
@@ -1,53 +1,162 @@
+use procfs::process::Process;
+
+use std::cmp::Reverse;
 use std::collections::{hash_map::Entry, HashMap};
-use std::collections::hash_map::IntoIter;
+use std::fmt::{Display, Error, Formatter};
+use std::result::Result;
+use std::vec::IntoIter;
 
+use crate::connection::{ConnectionTable, Flow};
+use crate::port::PortMapper;
 use crate::transfer::Transfer;
 
 pub type PID = i32;
 pub type Info = (Transfer, Vec<String>);
 
+// ConnectionList is what a `ConnectionTable` transferred per process: each flow is attributed to
+// the processes with a socket it matches, and whatever doesn't match any goes to `unknown`.
+#[derive(Debug, Clone)]
 pub struct ConnectionList {
   /// Map of PIDs to their transfers and identifiers.
   connections: HashMap<PID, Info>,
   /// A `Transfer` for unknown PIDs.
   unknown: Transfer,
+  /// Everything inserted, counting a flow shared by several processes once.
+  total: Transfer,
+}
+
+impl Default for ConnectionList {
+  fn default() -> ConnectionList {
+    ConnectionList::new()
+  }
 }
 
 impl ConnectionList {
   pub fn new() -> ConnectionList {
     ConnectionList {
       connections: HashMap::new(),
-      unknown: Transfer::new()
+      unknown: Transfer::new(),
+      total: Transfer::new(),
     }
   }
 
+  /// Attributes every flow in `table` using `port_mapper`.
+  pub fn from_table(table: &ConnectionTable, port_mapper: &PortMapper) -> ConnectionList {
+    let mut list = ConnectionList::new();
+    for (flow, transfer) in table {
+      list.insert_flow(flow, transfer, port_mapper);
+    }
+
+    list
+  }
+
+  /// Attributes `transfer` to each process with a socket `flow` matches, or to unknown if there
+  /// are none. Returns the processes, so callers can attribute it further (e.g., to users).
+  pub fn insert_flow<'a>(
+    &mut self,
+    flow: &Flow,
+    transfer: &Transfer,
+    port_mapper: &'a PortMapper,
+  ) -> Option<&'a Vec<Process>> {
+    let processes = port_mapper.get(flow.protocol, flow.local_addr, flow.local_port);
+    match processes {
+      Some(processes) => {
+        for process in processes {
+          match self.connections.entry(process.pid) {
+            Entry::Vacant(e) => {
+              let name = port_mapper
+                .name(process.pid)
+                .map_or_else(|| process.stat.comm.clone(), String::from);
+              e.insert((*transfer, vec![name]));
+            }
+            Entry::Occupied(mut e) => {
+              e.get_mut().0.merge(transfer);
+            }
+          }
+        }
+        self.total.merge(transfer);
+      }
+      None => self.insert_unknown(transfer),
+    }
+
+    processes
+  }
+
   pub fn insert(&mut self, pid: PID, transfer: &Transfer, process_name: String) {
     match self.connections.entry(pid) {
       Entry::Vacant(e) => {
-        e.insert((transfer.clone(), vec![process_name]));
+        e.insert((*transfer, vec![process_name]));
       }
       Entry::Occupied(mut e) => {
         let connections = e.get_mut();
         connections.0.merge(transfer);
-        connections.1.push(process_name);
+        if !connections.1.contains(&process_name) {
+          connections.1.push(process_name);
+        }
       }
     }
+    self.total.merge(transfer);
   }
 
   pub fn insert_unknown(&mut self, transfer: &Transfer) {
     self.unknown.merge(transfer);
+    self.total.merge(transfer);
+  }
+
+  pub fn get(&self, pid: PID) -> Option<&Info> {
+    self.connections.get(&pid)
   }
 
-  pub fn print(&self) {
-    // TODO: print out information -> maybe impl display?
+  pub fn unknown(&self) -> &Transfer {
+    &self.unknown
+  }
+
+  pub fn total(&self) -> &Transfer {
+    &self.total
+  }
+
+  pub fn len(&self) -> usize {
+    self.connections.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.connections.is_empty()
+  }
+
+  /// Processes with the most traffic first, and by PID when they're tied.
+  pub fn iter(&self) -> impl Iterator<Item = (PID, &Info)> {
+    let mut connections = self
+      .connections
+      .iter()
+      .map(|(pid, info)| (*pid, info))
+      .collect::<Vec<_>>();
+    connections.sort_by_key(|(pid, (transfer, _))| (Reverse(bytes(transfer)), *pid));
+    connections.into_iter()
   }
 }
 
 impl IntoIterator for ConnectionList {
   type Item = (PID, Info);
-  type IntoIter = IntoIter<PID, Info>;
+  type IntoIter = IntoIter<(PID, Info)>;
 
+  /// In the same order as `iter`.
   fn into_iter(self) -> Self::IntoIter {
-      self.connections.into_iter()
+    let mut connections = self.connections.into_iter().collect::<Vec<_>>();
+    connections.sort_by_key(|(pid, (transfer, _))| (Reverse(bytes(transfer)), *pid));
+    connections.into_iter()
   }
 }
+
+impl Display for ConnectionList {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    for (pid, (transfer, names)) in self.iter() {
+      writeln!(f, "PID:     [{:>6}] {} {}", pid, transfer, names.join(", "))?;
+    }
+    writeln!(f, "Unknown:          {}", self.unknown)?;
+    write!(f, "Total:            {}", self.total)
+  }
+}
+
+fn bytes(transfer: &Transfer) -> u64 {
+  transfer.incoming() + transfer.outgoing()
+}
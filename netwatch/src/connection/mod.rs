@@ -1,5 +1,9 @@
+pub mod flow;
+pub mod group;
 pub mod list;
 pub mod table;
 
+pub use flow::{Flow, Protocol};
+pub use group::{GroupList, Grouping, Memberships};
 pub use list::ConnectionList;
 pub use table::ConnectionTable;
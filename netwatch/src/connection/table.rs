@@ -1,12 +1,18 @@
 use std::collections::{hash_map::Entry, HashMap};
 
-use crate::port::Port;
+use crate::connection::Flow;
 use crate::transfer::Transfer;
 
-// ConnectionTable is a struct optimised for updating network usage for a specific port.
+// ConnectionTable is a struct optimised for updating network usage for a specific flow.
 // The packet handlers will write to it and the UI will read from it.
 pub struct ConnectionTable {
-  inner: HashMap<Port, Transfer>,
+  inner: HashMap<Flow, Transfer>,
+}
+
+impl Default for ConnectionTable {
+  fn default() -> ConnectionTable {
+    ConnectionTable::new()
+  }
 }
 
 impl ConnectionTable {
@@ -16,27 +22,25 @@ impl ConnectionTable {
     }
   }
 
-  fn incr(&mut self, is_incoming: bool, port: Port, size: u64) {
-    match self.inner.entry(port) {
-      Entry::Vacant(e) => {
-        e.insert(Transfer::new());
-      }
-      Entry::Occupied(mut e) => {
-        if is_incoming {
-          e.get_mut().incr_incoming(size);
-        } else {
-          e.get_mut().incr_outgoing(size);
-        }
-      }
+  fn incr(&mut self, is_incoming: bool, flow: Flow, size: u64) {
+    let transfer = match self.inner.entry(flow) {
+      Entry::Vacant(e) => e.insert(Transfer::new()),
+      Entry::Occupied(e) => e.into_mut(),
+    };
+
+    if is_incoming {
+      transfer.incr_incoming(size);
+    } else {
+      transfer.incr_outgoing(size);
     }
   }
 
-  pub fn incr_outgoing(&mut self, port: Port, size: u64) {
-    self.incr(false, port, size);
+  pub fn incr_outgoing(&mut self, flow: Flow, size: u64) {
+    self.incr(false, flow, size);
   }
 
-  pub fn incr_incoming(&mut self, port: Port, size: u64) {
-    self.incr(true, port, size);
+  pub fn incr_incoming(&mut self, flow: Flow, size: u64) {
+    self.incr(true, flow, size);
   }
 }
 
@@ -45,13 +49,13 @@ impl ConnectionTable {
 
 // structure helper for consuming iterator.
 pub struct IntoIteratorHelper {
-  iter: ::std::collections::hash_map::IntoIter<Port, Transfer>,
+  iter: ::std::collections::hash_map::IntoIter<Flow, Transfer>,
 }
 
 // implement the IntoIterator trait for a consuming iterator. Iteration will
 // consume the ConnectionTable structure
 impl IntoIterator for ConnectionTable {
-  type Item = (Port, Transfer);
+  type Item = (Flow, Transfer);
   type IntoIter = IntoIteratorHelper;
 
   // note that into_iter() is consuming self
@@ -64,7 +68,7 @@ impl IntoIterator for ConnectionTable {
 
 // now, implements Iterator trait for the helper struct, to be used by adapters
 impl Iterator for IntoIteratorHelper {
-  type Item = (Port, Transfer);
+  type Item = (Flow, Transfer);
 
   // just return the str reference
   fn next(&mut self) -> Option<Self::Item> {
@@ -76,13 +80,13 @@ impl Iterator for IntoIteratorHelper {
 
 // structure helper for non-consuming iterator.
 pub struct IterHelper<'a> {
-  iter: ::std::collections::hash_map::Iter<'a, Port, Transfer>,
+  iter: ::std::collections::hash_map::Iter<'a, Flow, Transfer>,
 }
 
 // implement the IntoIterator trait for a non-consuming iterator. Iteration will
 // borrow the ConnectionTable structure
 impl<'a> IntoIterator for &'a ConnectionTable {
-  type Item = (&'a Port, &'a Transfer);
+  type Item = (&'a Flow, &'a Transfer);
   type IntoIter = IterHelper<'a>;
 
   // note that into_iter() is consuming self
@@ -95,7 +99,7 @@ impl<'a> IntoIterator for &'a ConnectionTable {
 
 // now, implements Iterator trait for the helper struct, to be used by adapters
 impl<'a> Iterator for IterHelper<'a> {
-  type Item = (&'a Port, &'a Transfer);
+  type Item = (&'a Flow, &'a Transfer);
 
   // just return the str reference
   fn next(&mut self) -> Option<Self::Item> {
@@ -107,13 +111,13 @@ impl<'a> Iterator for IterHelper<'a> {
 
 // structure helper for mutable non-consuming iterator.
 pub struct IterMutHelper<'a> {
-  iter: ::std::collections::hash_map::IterMut<'a, Port, Transfer>,
+  iter: ::std::collections::hash_map::IterMut<'a, Flow, Transfer>,
 }
 
 // implement the IntoIterator trait for a mutable non-consuming iterator. Iteration will
 // mutably borrow the ConnectionTable structure
 impl<'a> IntoIterator for &'a mut ConnectionTable {
-  type Item = (&'a Port, &'a mut Transfer);
+  type Item = (&'a Flow, &'a mut Transfer);
   type IntoIter = IterMutHelper<'a>;
 
   // note that into_iter() is consuming self
@@ -126,7 +130,7 @@ impl<'a> IntoIterator for &'a mut ConnectionTable {
 
 // now, implements Iterator trait for the helper struct, to be used by adapters
 impl<'a> Iterator for IterMutHelper<'a> {
-  type Item = (&'a Port, &'a mut Transfer);
+  type Item = (&'a Flow, &'a mut Transfer);
 
   // just return the str reference
   fn next(&mut self) -> Option<Self::Item> {
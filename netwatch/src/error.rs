@@ -0,0 +1,85 @@
+use pcap_file::PcapError;
+use procfs::ProcError;
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::result;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+  /// Opening or receiving from a network interface failed.
+  Capture(io::Error),
+  /// Reading from `/proc` failed.
+  Procfs(ProcError),
+  /// We weren't allowed to do something, capturing usually needs root or `CAP_NET_RAW`.
+  Permission(String),
+  /// Data (e.g., a capture file or `/proc/pid_inode_map`) was malformed.
+  Parse(String),
+  /// Any other I/O failure, such as writing a recording.
+  Io(io::Error),
+}
+
+impl Error {
+  // Wraps an error from the capture channel, separating out permission failures.
+  pub(crate) fn capture(e: io::Error) -> Error {
+    match e.kind() {
+      io::ErrorKind::PermissionDenied => Error::Permission(e.to_string()),
+      _ => Error::Capture(e),
+    }
+  }
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Capture(e) => write!(f, "capture failed: {}", e),
+      Error::Procfs(e) => write!(f, "failed to read procfs: {}", e),
+      Error::Permission(msg) => write!(f, "permission denied: {}", msg),
+      Error::Parse(msg) => write!(f, "failed to parse: {}", msg),
+      Error::Io(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      Error::Capture(e) | Error::Io(e) => Some(e),
+      Error::Procfs(e) => Some(e),
+      Error::Permission(_) | Error::Parse(_) => None,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Error {
+    match e.kind() {
+      io::ErrorKind::PermissionDenied => Error::Permission(e.to_string()),
+      _ => Error::Io(e),
+    }
+  }
+}
+
+impl From<ProcError> for Error {
+  fn from(e: ProcError) -> Error {
+    match e {
+      ProcError::PermissionDenied(path) => Error::Permission(match path {
+        Some(path) => format!("unable to read {}", path.display()),
+        None => "unable to read procfs".into(),
+      }),
+      e => Error::Procfs(e),
+    }
+  }
+}
+
+impl From<PcapError> for Error {
+  fn from(e: PcapError) -> Error {
+    match e {
+      PcapError::IoError(e) => Error::from(e),
+      e => Error::Parse(e.to_string()),
+    }
+  }
+}
@@ -0,0 +1,630 @@
+use std::net::IpAddr;
+
+use crate::error::{Error, Result};
+use crate::filter::parse::{Direction, Expr, Primitive, Proto};
+use crate::filter::*;
+
+// Offsets into an Ethernet frame.
+const ETHERTYPE: u32 = 12;
+const IPV4_FLAGS_FRAGMENT: u32 = 20;
+const IPV4_PROTOCOL: u32 = 23;
+const IPV4_SOURCE: u32 = 26;
+const IPV4_DESTINATION: u32 = 30;
+const IPV6_NEXT_HEADER: u32 = 20;
+const IPV6_SOURCE: u32 = 22;
+const IPV6_DESTINATION: u32 = 38;
+const IPV6_PAYLOAD: u32 = 54;
+const ARP_SENDER_PROTO_ADDR: u32 = 28;
+const ARP_TARGET_PROTO_ADDR: u32 = 38;
+
+const ETHERTYPE_IPV4: u32 = 0x0800;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+const ETHERTYPE_ARP: u32 = 0x0806;
+
+const PROTOCOL_ICMP: u32 = 1;
+const PROTOCOL_TCP: u32 = 6;
+const PROTOCOL_UDP: u32 = 17;
+const PROTOCOL_ICMPV6: u32 = 58;
+
+// --- lowering
+
+// A filter expression lowered into tests on the raw frame.
+#[derive(Debug, Clone)]
+enum Cond {
+  All(Vec<Cond>),
+  Any(Vec<Cond>),
+  Not(Box<Cond>),
+  Check(Check),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Check {
+  size: u16,
+  offset: Offset,
+  mask: Option<u32>,
+  test: u16,
+  value: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Offset {
+  // From the start of the frame.
+  Frame(u32),
+  // From the end of the IPv4 header, whose length varies with its options.
+  Ipv4Payload(u32),
+}
+
+fn check(size: u16, offset: Offset, test: u16, value: u32) -> Cond {
+  Cond::Check(Check {
+    size,
+    offset,
+    mask: None,
+    test,
+    value,
+  })
+}
+
+fn masked(size: u16, offset: Offset, mask: u32, value: u32) -> Cond {
+  Cond::Check(Check {
+    size,
+    offset,
+    mask: Some(mask),
+    test: BPF_JEQ,
+    value: value & mask,
+  })
+}
+
+fn ethertype(value: u32) -> Cond {
+  check(BPF_H, Offset::Frame(ETHERTYPE), BPF_JEQ, value)
+}
+
+fn ipv4_protocol(protocols: &[u32]) -> Cond {
+  Cond::All(vec![
+    ethertype(ETHERTYPE_IPV4),
+    Cond::Any(
+      protocols
+        .iter()
+        .map(|p| check(BPF_B, Offset::Frame(IPV4_PROTOCOL), BPF_JEQ, *p))
+        .collect(),
+    ),
+  ])
+}
+
+fn ipv6_protocol(protocols: &[u32]) -> Cond {
+  Cond::All(vec![
+    ethertype(ETHERTYPE_IPV6),
+    Cond::Any(
+      protocols
+        .iter()
+        .map(|p| check(BPF_B, Offset::Frame(IPV6_NEXT_HEADER), BPF_JEQ, *p))
+        .collect(),
+    ),
+  ])
+}
+
+// Builds a condition for either/both ends of a packet, given a function for a single end.
+fn directed<F: Fn(bool) -> Cond>(direction: Direction, end: F) -> Cond {
+  match direction {
+    Direction::Src => end(true),
+    Direction::Dst => end(false),
+    Direction::Either => Cond::Any(vec![end(true), end(false)]),
+  }
+}
+
+// Compares an address against a network, one 32-bit word at a time.
+fn address(offset: u32, addr: &IpAddr, prefix: u8) -> Cond {
+  let octets = match addr {
+    IpAddr::V4(addr) => addr.octets().to_vec(),
+    IpAddr::V6(addr) => addr.octets().to_vec(),
+  };
+
+  Cond::All(
+    octets
+      .chunks(4)
+      .enumerate()
+      .filter_map(|(i, word)| {
+        let bits = (u32::from(prefix)).saturating_sub(i as u32 * 32).min(32);
+        if bits == 0 {
+          return None;
+        }
+
+        let mask = !0u32 << (32 - bits);
+        let value = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        Some(masked(
+          BPF_W,
+          Offset::Frame(offset + i as u32 * 4),
+          mask,
+          value,
+        ))
+      })
+      .collect(),
+  )
+}
+
+fn network(proto: Option<Proto>, direction: Direction, addr: &IpAddr, prefix: u8) -> Cond {
+  match addr {
+    IpAddr::V4(_) => {
+      let mut alternatives = vec![];
+      if proto != Some(Proto::Arp) {
+        alternatives.push(Cond::All(vec![
+          ethertype(ETHERTYPE_IPV4),
+          directed(direction, |src| {
+            let offset = if src { IPV4_SOURCE } else { IPV4_DESTINATION };
+            address(offset, addr, prefix)
+          }),
+        ]));
+      }
+      // Like tcpdump, an unqualified `host` also matches ARP packets.
+      if proto != Some(Proto::Ip) {
+        alternatives.push(Cond::All(vec![
+          ethertype(ETHERTYPE_ARP),
+          directed(direction, |src| {
+            let offset = if src {
+              ARP_SENDER_PROTO_ADDR
+            } else {
+              ARP_TARGET_PROTO_ADDR
+            };
+            address(offset, addr, prefix)
+          }),
+        ]));
+      }
+
+      Cond::Any(alternatives)
+    }
+    IpAddr::V6(_) => Cond::All(vec![
+      ethertype(ETHERTYPE_IPV6),
+      directed(direction, |src| {
+        let offset = if src { IPV6_SOURCE } else { IPV6_DESTINATION };
+        address(offset, addr, prefix)
+      }),
+    ]),
+  }
+}
+
+fn port_range(proto: Option<Proto>, direction: Direction, low: u16, high: u16) -> Cond {
+  let protocols = match proto {
+    Some(Proto::Tcp) => vec![PROTOCOL_TCP],
+    Some(Proto::Udp) => vec![PROTOCOL_UDP],
+    _ => vec![PROTOCOL_TCP, PROTOCOL_UDP],
+  };
+
+  let port = |offset| {
+    if low == high {
+      check(BPF_H, offset, BPF_JEQ, u32::from(low))
+    } else {
+      Cond::All(vec![
+        check(BPF_H, offset, BPF_JGE, u32::from(low)),
+        Cond::Not(Box::new(check(BPF_H, offset, BPF_JGT, u32::from(high)))),
+      ])
+    }
+  };
+
+  Cond::Any(vec![
+    Cond::All(vec![
+      ipv4_protocol(&protocols),
+      Cond::Any(vec![
+        // Only the first fragment of a datagram contains the transport header, so the rest are let
+        // through for reassembly to attribute along with it (unlike tcpdump, which drops them).
+        check(
+          BPF_H,
+          Offset::Frame(IPV4_FLAGS_FRAGMENT),
+          BPF_JSET,
+          0x1fff,
+        ),
+        directed(direction, |src| {
+          port(Offset::Ipv4Payload(if src { 0 } else { 2 }))
+        }),
+      ]),
+    ]),
+    Cond::All(vec![
+      ipv6_protocol(&protocols),
+      directed(direction, |src| {
+        port(Offset::Frame(IPV6_PAYLOAD + if src { 0 } else { 2 }))
+      }),
+    ]),
+  ])
+}
+
+fn lower(expr: &Expr) -> Cond {
+  match expr {
+    Expr::And(a, b) => Cond::All(vec![lower(a), lower(b)]),
+    Expr::Or(a, b) => Cond::Any(vec![lower(a), lower(b)]),
+    Expr::Not(a) => Cond::Not(Box::new(lower(a))),
+    Expr::Primitive(primitive) => match primitive {
+      Primitive::Proto(Proto::Ip) => ethertype(ETHERTYPE_IPV4),
+      Primitive::Proto(Proto::Ip6) => ethertype(ETHERTYPE_IPV6),
+      Primitive::Proto(Proto::Arp) => ethertype(ETHERTYPE_ARP),
+      Primitive::Proto(Proto::Tcp) => Cond::Any(vec![
+        ipv4_protocol(&[PROTOCOL_TCP]),
+        ipv6_protocol(&[PROTOCOL_TCP]),
+      ]),
+      Primitive::Proto(Proto::Udp) => Cond::Any(vec![
+        ipv4_protocol(&[PROTOCOL_UDP]),
+        ipv6_protocol(&[PROTOCOL_UDP]),
+      ]),
+      Primitive::Proto(Proto::Icmp) => ipv4_protocol(&[PROTOCOL_ICMP]),
+      Primitive::Proto(Proto::Icmp6) => ipv6_protocol(&[PROTOCOL_ICMPV6]),
+      Primitive::Host(proto, direction, addr) => {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        network(*proto, *direction, addr, prefix)
+      }
+      Primitive::Net(proto, direction, addr, prefix) => network(*proto, *direction, addr, *prefix),
+      Primitive::PortRange(proto, direction, low, high) => {
+        port_range(*proto, *direction, *low, *high)
+      }
+    },
+  }
+}
+
+// --- code generation
+
+type Label = usize;
+
+// An instruction whose jump targets haven't been resolved yet.
+struct Pending {
+  code: u16,
+  k: u32,
+  jt: Option<Label>,
+  jf: Option<Label>,
+}
+
+struct Generator {
+  instructions: Vec<Pending>,
+  labels: Vec<Option<usize>>,
+}
+
+impl Generator {
+  fn label(&mut self) -> Label {
+    self.labels.push(None);
+    self.labels.len() - 1
+  }
+
+  fn place(&mut self, label: Label) {
+    self.labels[label] = Some(self.instructions.len());
+  }
+
+  fn emit(&mut self, code: u16, k: u32) {
+    self.instructions.push(Pending {
+      code,
+      k,
+      jt: None,
+      jf: None,
+    });
+  }
+
+  fn emit_jump(&mut self, code: u16, k: u32, jt: Label, jf: Label) {
+    self.instructions.push(Pending {
+      code,
+      k,
+      jt: Some(jt),
+      jf: Some(jf),
+    });
+  }
+
+  // Emits code that jumps to `on_true` if `cond` holds, and `on_false` otherwise.
+  // Labels are always placed after the code that refers to them, since BPF can only jump forward.
+  fn branch(&mut self, cond: &Cond, on_true: Label, on_false: Label) {
+    match cond {
+      Cond::All(conds) => self.sequence(conds, on_true, on_false, true),
+      Cond::Any(conds) => self.sequence(conds, on_true, on_false, false),
+      Cond::Not(cond) => self.branch(cond, on_false, on_true),
+      Cond::Check(check) => {
+        match check.offset {
+          Offset::Frame(offset) => self.emit(BPF_LD | check.size | BPF_ABS, offset),
+          Offset::Ipv4Payload(offset) => {
+            // X = IPv4 header length, then load relative to it.
+            self.emit(BPF_LDX | BPF_B | BPF_MSH, 14);
+            self.emit(BPF_LD | check.size | BPF_IND, 14 + offset);
+          }
+        }
+
+        if let Some(mask) = check.mask {
+          self.emit(BPF_ALU | BPF_AND | BPF_K, mask);
+        }
+
+        self.emit_jump(BPF_JMP | check.test | BPF_K, check.value, on_true, on_false);
+      }
+    }
+  }
+
+  fn sequence(&mut self, conds: &[Cond], on_true: Label, on_false: Label, all: bool) {
+    match conds.split_last() {
+      None => {
+        // An empty `All` is true, and an empty `Any` is false.
+        let target = if all { on_true } else { on_false };
+        self.instructions.push(Pending {
+          code: BPF_JMP | BPF_JA,
+          k: 0,
+          jt: Some(target),
+          jf: None,
+        });
+      }
+      Some((last, rest)) => {
+        for cond in rest {
+          let next = self.label();
+          if all {
+            self.branch(cond, next, on_false);
+          } else {
+            self.branch(cond, on_true, next);
+          }
+          self.place(next);
+        }
+        self.branch(last, on_true, on_false);
+      }
+    }
+  }
+
+  fn resolve(self) -> Result<Vec<Instruction>> {
+    let labels = self.labels;
+    let offset = |from: usize, label: Option<Label>| match label.and_then(|label| labels[label]) {
+      Some(to) => (to - from - 1) as u32,
+      None => 0,
+    };
+
+    let mut program = vec![];
+    for (i, pending) in self.instructions.iter().enumerate() {
+      let instruction = if pending.code == BPF_JMP | BPF_JA {
+        Instruction::new(pending.code, 0, 0, offset(i, pending.jt))
+      } else {
+        let (jt, jf) = (offset(i, pending.jt), offset(i, pending.jf));
+        if jt > 255 || jf > 255 {
+          return Err(Error::Parse(
+            "invalid filter: expression is too complex".into(),
+          ));
+        }
+        Instruction::new(pending.code, jt as u8, jf as u8, pending.k)
+      };
+      program.push(instruction);
+    }
+
+    Ok(program)
+  }
+}
+
+pub fn compile(expr: Option<&Expr>) -> Result<Vec<Instruction>> {
+  let expr = match expr {
+    Some(expr) => expr,
+    // An empty filter accepts everything.
+    None => return Ok(vec![Instruction::new(BPF_RET | BPF_K, 0, 0, SNAPLEN)]),
+  };
+
+  let mut generator = Generator {
+    instructions: vec![],
+    labels: vec![],
+  };
+
+  let (accept, reject) = (generator.label(), generator.label());
+  generator.branch(&lower(expr), accept, reject);
+  generator.place(accept);
+  generator.emit(BPF_RET | BPF_K, SNAPLEN);
+  generator.place(reject);
+  generator.emit(BPF_RET | BPF_K, 0);
+
+  generator.resolve()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ethernet(ethertype: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2];
+    frame.extend_from_slice(&(ethertype as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+  }
+
+  // An IPv4 packet, with `options` making its header longer and `fragment` being the flags and
+  // fragment offset field.
+  fn ipv4(
+    protocol: u32,
+    source: [u8; 4],
+    destination: [u8; 4],
+    options: &[u8],
+    fragment: u16,
+    payload: &[u8],
+  ) -> Vec<u8> {
+    let header_len = 20 + options.len();
+    let mut packet = vec![0x40 | (header_len / 4) as u8, 0];
+    packet.extend_from_slice(&((header_len + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&fragment.to_be_bytes());
+    packet.extend_from_slice(&[64, protocol as u8, 0, 0]);
+    packet.extend_from_slice(&source);
+    packet.extend_from_slice(&destination);
+    packet.extend_from_slice(options);
+    packet.extend_from_slice(payload);
+    ethernet(ETHERTYPE_IPV4, &packet)
+  }
+
+  fn ipv6(next_header: u32, source: &str, destination: &str, payload: &[u8]) -> Vec<u8> {
+    let parse = |addr: &str| addr.parse::<std::net::Ipv6Addr>().unwrap().octets();
+    let mut packet = vec![0x60, 0, 0, 0];
+    packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[next_header as u8, 64]);
+    packet.extend_from_slice(&parse(source));
+    packet.extend_from_slice(&parse(destination));
+    packet.extend_from_slice(payload);
+    ethernet(ETHERTYPE_IPV6, &packet)
+  }
+
+  fn arp(sender: [u8; 4], target: [u8; 4]) -> Vec<u8> {
+    let mut packet = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1];
+    packet.extend_from_slice(&[0x02, 0, 0, 0, 0, 2]);
+    packet.extend_from_slice(&sender);
+    packet.extend_from_slice(&[0; 6]);
+    packet.extend_from_slice(&target);
+    ethernet(ETHERTYPE_ARP, &packet)
+  }
+
+  // The start of a TCP or UDP header.
+  fn ports(source: u16, destination: u16) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(&source.to_be_bytes());
+    header.extend_from_slice(&destination.to_be_bytes());
+    header.extend_from_slice(&[0; 16]);
+    header
+  }
+
+  fn filter(expression: &str) -> Filter {
+    Filter::new(expression).unwrap()
+  }
+
+  const LOCAL: [u8; 4] = [10, 0, 0, 1];
+  const REMOTE: [u8; 4] = [93, 184, 216, 34];
+
+  #[test]
+  fn same_program_as_tcpdump() {
+    // `tcpdump -dd ip`
+    assert_eq!(
+      filter("ip").program(),
+      &[
+        Instruction::new(0x28, 0, 0, 0x0000_000c),
+        Instruction::new(0x15, 0, 1, 0x0000_0800),
+        Instruction::new(0x06, 0, 0, 0x0004_0000),
+        Instruction::new(0x06, 0, 0, 0x0000_0000),
+      ]
+    );
+    assert_eq!(
+      filter("").program(),
+      &[Instruction::new(0x06, 0, 0, 0x0004_0000)]
+    );
+  }
+
+  #[test]
+  fn protocols() {
+    let tcp = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[], 0, &ports(40000, 443));
+    let udp = ipv4(PROTOCOL_UDP, LOCAL, REMOTE, &[], 0, &ports(40000, 53));
+    let tcp6 = ipv6(
+      PROTOCOL_TCP,
+      "2001:db8::1",
+      "2001:db8::2",
+      &ports(40000, 443),
+    );
+    let icmp6 = ipv6(PROTOCOL_ICMPV6, "fe80::1", "ff02::1", &[128, 0, 0, 0]);
+
+    assert!(filter("tcp").matches(&tcp));
+    assert!(filter("tcp").matches(&tcp6));
+    assert!(!filter("tcp").matches(&udp));
+    assert!(filter("ip").matches(&udp));
+    assert!(!filter("ip").matches(&tcp6));
+    assert!(filter("ip6").matches(&icmp6));
+    assert!(filter("icmp6").matches(&icmp6));
+    assert!(!filter("icmp").matches(&icmp6));
+    assert!(filter("arp").matches(&arp(LOCAL, REMOTE)));
+    assert!(filter("").matches(&udp));
+  }
+
+  #[test]
+  fn hosts_and_networks() {
+    let outgoing = ipv4(PROTOCOL_UDP, LOCAL, REMOTE, &[], 0, &ports(40000, 53));
+    let request = arp(REMOTE, LOCAL);
+
+    assert!(filter("host 10.0.0.1").matches(&outgoing));
+    assert!(filter("src host 10.0.0.1").matches(&outgoing));
+    assert!(!filter("dst host 10.0.0.1").matches(&outgoing));
+    // Like tcpdump, `host` matches ARP too, unless it's qualified with `ip`.
+    assert!(filter("dst host 10.0.0.1").matches(&request));
+    assert!(!filter("ip host 10.0.0.1").matches(&request));
+    assert!(filter("arp src host 93.184.216.34").matches(&request));
+
+    assert!(filter("net 10.0.0.0/8").matches(&outgoing));
+    assert!(filter("dst net 93.184.0.0/16").matches(&outgoing));
+    assert!(!filter("dst net 93.185.0.0/16").matches(&outgoing));
+    assert!(filter("net 0.0.0.0/0").matches(&outgoing));
+
+    let link_local = ipv6(PROTOCOL_UDP, "fe80::1", "2001:db8::2", &ports(546, 547));
+    assert!(filter("src net fe80::/10").matches(&link_local));
+    assert!(!filter("dst net fe80::/10").matches(&link_local));
+    assert!(filter("host 2001:db8::2").matches(&link_local));
+    assert!(!filter("host 10.0.0.1").matches(&link_local));
+  }
+
+  #[test]
+  fn ports_and_ranges() {
+    let https = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[], 0, &ports(40000, 443));
+    assert!(filter("port 443").matches(&https));
+    assert!(filter("tcp dst port 443").matches(&https));
+    assert!(!filter("tcp src port 443").matches(&https));
+    assert!(!filter("udp port 443").matches(&https));
+
+    // The ports come after the IPv4 header's options.
+    let with_options = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[1; 8], 0, &ports(40000, 443));
+    assert!(filter("port 443").matches(&with_options));
+    assert!(!filter("port 257").matches(&with_options));
+
+    // Only the first fragment has the transport header, so later ones match any port of their
+    // protocol, whatever they happen to contain.
+    let first = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[], 0x2000, &ports(40000, 443));
+    let later = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[], 0x0010, &[0; 16]);
+    assert!(filter("port 443").matches(&first));
+    assert!(!filter("port 80").matches(&first));
+    assert!(filter("port 443").matches(&later));
+    assert!(filter("tcp dst port 80").matches(&later));
+    assert!(!filter("udp port 443").matches(&later));
+
+    let ipv6 = ipv6(PROTOCOL_UDP, "2001:db8::1", "2001:db8::2", &ports(5353, 53));
+    assert!(filter("udp port 53").matches(&ipv6));
+    assert!(filter("src port 5353").matches(&ipv6));
+
+    let range = filter("portrange 1000-2000");
+    for (port, matches) in [(999, false), (1000, true), (2000, true), (2001, false)].iter() {
+      let frame = ipv4(PROTOCOL_UDP, LOCAL, REMOTE, &[], 0, &ports(*port, *port));
+      assert_eq!(range.matches(&frame), *matches, "port {}", port);
+    }
+  }
+
+  #[test]
+  fn combinations() {
+    let dns = ipv4(PROTOCOL_UDP, LOCAL, REMOTE, &[], 0, &ports(40000, 53));
+    let https = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[], 0, &ports(40000, 443));
+
+    let filter = filter("(udp and port 53) or (tcp and not dst port 443)");
+    assert!(filter.matches(&dns));
+    assert!(!filter.matches(&https));
+    assert!(!filter.matches(&arp(LOCAL, REMOTE)));
+  }
+
+  #[test]
+  fn vlan_tags_are_skipped() {
+    let untagged = ipv4(PROTOCOL_UDP, REMOTE, LOCAL, &[], 0, &ports(53, 5353));
+    let tag = |frame: &[u8], ethertype: u16, id: u16| {
+      let mut tagged = frame[..12].to_vec();
+      tagged.extend_from_slice(&ethertype.to_be_bytes());
+      tagged.extend_from_slice(&id.to_be_bytes());
+      tagged.extend_from_slice(&frame[12..]);
+      tagged
+    };
+    let tagged = tag(&untagged, 0x8100, 10);
+    let qinq = tag(&tagged, 0x88a8, 100);
+
+    for expression in ["udp port 5353", "src host 93.184.216.34", "ip"].iter() {
+      let filter = filter(expression);
+      assert!(filter.matches(&untagged));
+      assert!(filter.matches(&tagged), "{}", expression);
+      assert!(filter.matches(&qinq), "{}", expression);
+    }
+    assert!(!filter("tcp").matches(&tagged));
+
+    // A tag without anything after it.
+    assert!(!filter("ip").matches(&tagged[..16]));
+  }
+
+  #[test]
+  fn truncated_frames_are_rejected() {
+    let https = ipv4(PROTOCOL_TCP, LOCAL, REMOTE, &[], 0, &ports(40000, 443));
+    assert!(!filter("port 443").matches(&https[..35]));
+    assert!(!filter("ip").matches(&https[..13]));
+  }
+
+  #[test]
+  fn too_complex() {
+    let hosts = (0..100)
+      .map(|i| format!("host 10.0.0.{}", i))
+      .collect::<Vec<_>>();
+    match Filter::new(&hosts.join(" or ")) {
+      Err(Error::Parse(msg)) => assert_eq!(msg, "invalid filter: expression is too complex"),
+      other => panic!("expected the filter to be too complex, got {:?}", other),
+    }
+  }
+}
@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+
+use crate::error::Result;
+
+mod compile;
+mod parse;
+
+// Classic BPF opcodes (see linux/filter.h), only the ones we generate or interpret.
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MSH: u16 = 0xa0;
+
+const BPF_AND: u16 = 0x50;
+
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+
+const BPF_K: u16 = 0x00;
+
+// How many bytes of a matching packet to accept.
+const SNAPLEN: u32 = 262_144;
+
+// Where the ethertype goes in an Ethernet frame, the ethertypes of VLAN tags (802.1Q, and the
+// outer tag of QinQ, both standard and legacy), and how long a tag is.
+const ETHERTYPE_OFFSET: usize = 12;
+const VLAN_ETHERTYPES: [u16; 3] = [0x8100, 0x88a8, 0x9100];
+const VLAN_TAG_LEN: usize = 4;
+
+/// A single classic BPF instruction, laid out like the kernel's `struct sock_filter`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Instruction {
+  pub code: u16,
+  pub jt: u8,
+  pub jf: u8,
+  pub k: u32,
+}
+
+impl Instruction {
+  fn new(code: u16, jt: u8, jf: u8, k: u32) -> Instruction {
+    Instruction { code, jt, jf, k }
+  }
+}
+
+/// A program that accepts nothing.
+pub(crate) const DROP_ALL: &[Instruction] = &[Instruction {
+  code: BPF_RET | BPF_K,
+  jt: 0,
+  jf: 0,
+  k: 0,
+}];
+
+// Same format as `tcpdump -dd`.
+impl Display for Instruction {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{{ 0x{:x}, {}, {}, 0x{:08x} }}",
+      self.code, self.jt, self.jf, self.k
+    )
+  }
+}
+
+/// A tcpdump-style capture filter (e.g., `tcp port 443 and not host 10.0.0.1`) compiled to
+/// classic BPF. On Linux the program is attached to the capture socket so the kernel drops
+/// non-matching frames, everywhere else (and when replaying) it's run by `matches`.
+#[derive(Debug, Clone)]
+pub struct Filter {
+  expression: String,
+  program: Vec<Instruction>,
+}
+
+impl Filter {
+  pub fn new(expression: &str) -> Result<Filter> {
+    let expr = parse::parse(expression)?;
+    let program = compile::compile(expr.as_ref())?;
+
+    Ok(Filter {
+      expression: expression.into(),
+      program,
+    })
+  }
+
+  pub fn expression(&self) -> &str {
+    &self.expression
+  }
+
+  pub fn program(&self) -> &[Instruction] {
+    &self.program
+  }
+
+  /// Runs the program against an Ethernet frame. Programs expect frames without VLAN tags, since
+  /// that's how the kernel filters them (it takes the tags out first), so they're skipped.
+  pub fn matches(&self, frame: &[u8]) -> bool {
+    let frame = untagged(frame);
+    let load = |offset: u32, size: u16| -> Option<u32> {
+      let offset = offset as usize;
+      let bytes = frame.get(offset..offset + size_of(size))?;
+      Some(
+        bytes
+          .iter()
+          .fold(0, |acc, byte| acc << 8 | u32::from(*byte)),
+      )
+    };
+
+    let (mut a, mut x) = (0u32, 0u32);
+    let mut pc = 0;
+    while let Some(instruction) = self.program.get(pc) {
+      let Instruction { code, jt, jf, k } = *instruction;
+      pc += 1;
+
+      match code & 0x07 {
+        BPF_LD => {
+          let offset = match code & 0xe0 {
+            BPF_ABS => k,
+            BPF_IND => x.wrapping_add(k),
+            _ => return false,
+          };
+          // Like the kernel, reading past the end of the packet rejects it.
+          a = match load(offset, code & 0x18) {
+            Some(value) => value,
+            None => return false,
+          };
+        }
+        BPF_LDX if code == BPF_LDX | BPF_B | BPF_MSH => {
+          x = match load(k, BPF_B) {
+            Some(value) => (value & 0x0f) * 4,
+            None => return false,
+          };
+        }
+        BPF_ALU if code == BPF_ALU | BPF_AND | BPF_K => a &= k,
+        BPF_JMP => {
+          let taken = match code & 0xf0 {
+            BPF_JA => {
+              pc += k as usize;
+              continue;
+            }
+            BPF_JEQ => a == k,
+            BPF_JGT => a > k,
+            BPF_JGE => a >= k,
+            BPF_JSET => a & k != 0,
+            _ => return false,
+          };
+          pc += if taken { jt } else { jf } as usize;
+        }
+        BPF_RET => return k != 0,
+        _ => return false,
+      }
+    }
+
+    false
+  }
+}
+
+// `frame` without any VLAN tags.
+fn untagged(frame: &[u8]) -> Cow<'_, [u8]> {
+  let mut frame = Cow::Borrowed(frame);
+  loop {
+    let ethertype = match frame.get(ETHERTYPE_OFFSET..ETHERTYPE_OFFSET + 2) {
+      Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+      None => return frame,
+    };
+    if !VLAN_ETHERTYPES.contains(&ethertype) || frame.len() < ETHERTYPE_OFFSET + VLAN_TAG_LEN + 2 {
+      return frame;
+    }
+
+    // The tag ends with the ethertype of what it carries, which takes the tag's place.
+    frame
+      .to_mut()
+      .drain(ETHERTYPE_OFFSET..ETHERTYPE_OFFSET + VLAN_TAG_LEN);
+  }
+}
+
+fn size_of(size: u16) -> usize {
+  match size {
+    BPF_W => 4,
+    BPF_H => 2,
+    _ => 1,
+  }
+}
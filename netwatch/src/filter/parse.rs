@@ -0,0 +1,425 @@
+use std::net::IpAddr;
+
+use crate::error::{Error, Result};
+
+// Parses a tcpdump-style filter expression, e.g. `tcp port 443 and not host 10.0.0.1`.
+//
+// Supported grammar (a subset of pcap-filter(7)):
+//
+//  expr      := and (("or" | "||") and)*
+//  and       := unary (("and" | "&&") unary)*
+//  unary     := ("not" | "!") unary | "(" expr ")" | primitive
+//  primitive := proto
+//             | [proto] [src | dst] (host ADDR | net ADDR/LEN | port N | portrange N-M)
+//  proto     := ip | ip6 | arp | tcp | udp | icmp | icmp6
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+  Not(Box<Expr>),
+  Primitive(Primitive),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Proto {
+  Ip,
+  Ip6,
+  Arp,
+  Tcp,
+  Udp,
+  Icmp,
+  Icmp6,
+}
+
+impl Proto {
+  fn name(&self) -> &'static str {
+    match self {
+      Proto::Ip => "ip",
+      Proto::Ip6 => "ip6",
+      Proto::Arp => "arp",
+      Proto::Tcp => "tcp",
+      Proto::Udp => "udp",
+      Proto::Icmp => "icmp",
+      Proto::Icmp6 => "icmp6",
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+  Src,
+  Dst,
+  Either,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Primitive {
+  Proto(Proto),
+  Host(Option<Proto>, Direction, IpAddr),
+  Net(Option<Proto>, Direction, IpAddr, u8),
+  PortRange(Option<Proto>, Direction, u16, u16),
+}
+
+pub fn parse(expression: &str) -> Result<Option<Expr>> {
+  let tokens = tokenize(expression);
+  if tokens.is_empty() {
+    return Ok(None);
+  }
+
+  let mut parser = Parser {
+    tokens,
+    position: 0,
+  };
+  let expr = parser.expr()?;
+  match parser.peek() {
+    None => Ok(Some(expr)),
+    Some(token) => Err(invalid(format!("unexpected '{}'", token))),
+  }
+}
+
+fn tokenize(expression: &str) -> Vec<String> {
+  let mut tokens = vec![];
+  let mut current = String::new();
+  let mut chars = expression.chars().peekable();
+  while let Some(c) = chars.next() {
+    let symbol = match c {
+      '(' | ')' | '!' => Some(c.to_string()),
+      '&' | '|' if chars.peek() == Some(&c) => {
+        chars.next();
+        Some(format!("{}{}", c, c))
+      }
+      _ => None,
+    };
+
+    if symbol.is_some() || c.is_whitespace() {
+      if !current.is_empty() {
+        tokens.push(current.split_off(0));
+      }
+      tokens.extend(symbol);
+    } else {
+      current.push(c);
+    }
+  }
+
+  if !current.is_empty() {
+    tokens.push(current);
+  }
+
+  tokens
+}
+
+struct Parser {
+  tokens: Vec<String>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&str> {
+    self.tokens.get(self.position).map(|token| token.as_str())
+  }
+
+  fn next(&mut self) -> Option<String> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  fn eat(&mut self, alternatives: &[&str]) -> bool {
+    match self.peek() {
+      Some(token) if alternatives.contains(&token) => {
+        self.position += 1;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  fn expr(&mut self) -> Result<Expr> {
+    let mut expr = self.and()?;
+    while self.eat(&["or", "||"]) {
+      expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+    }
+
+    Ok(expr)
+  }
+
+  fn and(&mut self) -> Result<Expr> {
+    let mut expr = self.unary()?;
+    while self.eat(&["and", "&&"]) {
+      expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+    }
+
+    Ok(expr)
+  }
+
+  fn unary(&mut self) -> Result<Expr> {
+    if self.eat(&["not", "!"]) {
+      return Ok(Expr::Not(Box::new(self.unary()?)));
+    }
+
+    if self.eat(&["("]) {
+      let expr = self.expr()?;
+      if !self.eat(&[")"]) {
+        return Err(invalid("expected ')'"));
+      }
+
+      return Ok(expr);
+    }
+
+    self.primitive().map(Expr::Primitive)
+  }
+
+  fn primitive(&mut self) -> Result<Primitive> {
+    let proto = match self.peek() {
+      Some("ip") => Some(Proto::Ip),
+      Some("ip6") => Some(Proto::Ip6),
+      Some("arp") => Some(Proto::Arp),
+      Some("tcp") => Some(Proto::Tcp),
+      Some("udp") => Some(Proto::Udp),
+      Some("icmp") => Some(Proto::Icmp),
+      Some("icmp6") => Some(Proto::Icmp6),
+      _ => None,
+    };
+    if proto.is_some() {
+      self.position += 1;
+    }
+
+    let direction = if self.eat(&["src"]) {
+      Direction::Src
+    } else if self.eat(&["dst"]) {
+      Direction::Dst
+    } else {
+      Direction::Either
+    };
+
+    let kind = match self.peek() {
+      Some("host") | Some("net") | Some("port") | Some("portrange") => self.next().unwrap(),
+      // A protocol on its own (e.g. `tcp`) is a primitive too.
+      _ => match (proto, direction) {
+        (Some(proto), Direction::Either) => return Ok(Primitive::Proto(proto)),
+        _ => {
+          return Err(invalid(match self.peek() {
+            Some(token) => format!("unexpected '{}'", token),
+            None => "unexpected end of expression".into(),
+          }))
+        }
+      },
+    };
+
+    let value = self
+      .next()
+      .ok_or_else(|| invalid(format!("expected a value after '{}'", kind)))?;
+
+    match kind.as_str() {
+      "host" => {
+        let addr = parse_addr(&value)?;
+        check_family(proto, &addr)?;
+        Ok(Primitive::Host(proto, direction, addr))
+      }
+      "net" => {
+        let (addr, prefix) = parse_net(&value)?;
+        check_family(proto, &addr)?;
+        Ok(Primitive::Net(proto, direction, addr, prefix))
+      }
+      "port" => {
+        check_transport(proto, &kind)?;
+        let port = parse_port(&value)?;
+        Ok(Primitive::PortRange(proto, direction, port, port))
+      }
+      _ => {
+        check_transport(proto, &kind)?;
+        let mut bounds = value.splitn(2, '-');
+        let low = parse_port(bounds.next().unwrap_or(""))?;
+        let high = parse_port(bounds.next().unwrap_or(""))?;
+        if low > high {
+          return Err(invalid(format!("invalid port range '{}'", value)));
+        }
+
+        Ok(Primitive::PortRange(proto, direction, low, high))
+      }
+    }
+  }
+}
+
+fn parse_addr(value: &str) -> Result<IpAddr> {
+  value
+    .parse()
+    .map_err(|_| invalid(format!("invalid address '{}'", value)))
+}
+
+fn parse_net(value: &str) -> Result<(IpAddr, u8)> {
+  let mut parts = value.splitn(2, '/');
+  let addr = parse_addr(parts.next().unwrap_or(""))?;
+  let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+  let prefix = match parts.next() {
+    Some(prefix) => prefix
+      .parse::<u8>()
+      .ok()
+      .filter(|prefix| *prefix <= max_prefix)
+      .ok_or_else(|| invalid(format!("invalid network '{}'", value)))?,
+    None => max_prefix,
+  };
+
+  Ok((addr, prefix))
+}
+
+fn parse_port(value: &str) -> Result<u16> {
+  value
+    .parse()
+    .map_err(|_| invalid(format!("invalid port '{}'", value)))
+}
+
+fn check_family(proto: Option<Proto>, addr: &IpAddr) -> Result<()> {
+  match (proto, addr) {
+    (None, _) | (Some(Proto::Ip), IpAddr::V4(_)) | (Some(Proto::Ip6), IpAddr::V6(_)) => Ok(()),
+    (Some(Proto::Arp), IpAddr::V4(_)) => Ok(()),
+    (Some(proto), _) => Err(invalid(format!(
+      "'{}' can't be used with {}",
+      addr,
+      proto.name()
+    ))),
+  }
+}
+
+fn check_transport(proto: Option<Proto>, kind: &str) -> Result<()> {
+  match proto {
+    None | Some(Proto::Tcp) | Some(Proto::Udp) => Ok(()),
+    Some(proto) => Err(invalid(format!(
+      "'{}' can't be used with {}",
+      kind,
+      proto.name()
+    ))),
+  }
+}
+
+fn invalid<S: Into<String>>(msg: S) -> Error {
+  Error::Parse(format!("invalid filter: {}", msg.into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn host(proto: Option<Proto>, direction: Direction, addr: &str) -> Expr {
+    Expr::Primitive(Primitive::Host(proto, direction, addr.parse().unwrap()))
+  }
+
+  fn error(expression: &str) -> String {
+    match parse(expression) {
+      Err(Error::Parse(msg)) => msg,
+      other => panic!("expected a parse error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn empty() {
+    assert_eq!(parse("").unwrap(), None);
+    assert_eq!(parse("   ").unwrap(), None);
+  }
+
+  #[test]
+  fn primitives() {
+    assert_eq!(
+      parse("tcp").unwrap(),
+      Some(Expr::Primitive(Primitive::Proto(Proto::Tcp)))
+    );
+    assert_eq!(
+      parse("src host 10.0.0.1").unwrap(),
+      Some(host(None, Direction::Src, "10.0.0.1"))
+    );
+    assert_eq!(
+      parse("ip6 dst net fe80::/10").unwrap(),
+      Some(Expr::Primitive(Primitive::Net(
+        Some(Proto::Ip6),
+        Direction::Dst,
+        "fe80::".parse().unwrap(),
+        10
+      )))
+    );
+    assert_eq!(
+      parse("net 192.168.0.0").unwrap(),
+      Some(Expr::Primitive(Primitive::Net(
+        None,
+        Direction::Either,
+        "192.168.0.0".parse().unwrap(),
+        32
+      )))
+    );
+    assert_eq!(
+      parse("udp port 53").unwrap(),
+      Some(Expr::Primitive(Primitive::PortRange(
+        Some(Proto::Udp),
+        Direction::Either,
+        53,
+        53
+      )))
+    );
+    assert_eq!(
+      parse("portrange 6000-6010").unwrap(),
+      Some(Expr::Primitive(Primitive::PortRange(
+        None,
+        Direction::Either,
+        6000,
+        6010
+      )))
+    );
+  }
+
+  #[test]
+  fn precedence() {
+    // `and` binds tighter than `or`, and `not` tighter than both.
+    let a = host(None, Direction::Either, "10.0.0.1");
+    let b = host(None, Direction::Either, "10.0.0.2");
+    let c = host(None, Direction::Either, "10.0.0.3");
+    assert_eq!(
+      parse("host 10.0.0.1 or host 10.0.0.2 and not host 10.0.0.3").unwrap(),
+      Some(Expr::Or(
+        Box::new(a.clone()),
+        Box::new(Expr::And(
+          Box::new(b.clone()),
+          Box::new(Expr::Not(Box::new(c.clone())))
+        ))
+      ))
+    );
+    assert_eq!(
+      parse("(host 10.0.0.1 || host 10.0.0.2)&&!host 10.0.0.3").unwrap(),
+      Some(Expr::And(
+        Box::new(Expr::Or(Box::new(a), Box::new(b))),
+        Box::new(Expr::Not(Box::new(c)))
+      ))
+    );
+  }
+
+  #[test]
+  fn errors() {
+    assert_eq!(
+      error("tcp port"),
+      "invalid filter: expected a value after 'port'"
+    );
+    assert_eq!(
+      error("host 10.0.0"),
+      "invalid filter: invalid address '10.0.0'"
+    );
+    assert_eq!(
+      error("net 10.0.0.0/33"),
+      "invalid filter: invalid network '10.0.0.0/33'"
+    );
+    assert_eq!(error("port 65536"), "invalid filter: invalid port '65536'");
+    assert_eq!(
+      error("portrange 10-1"),
+      "invalid filter: invalid port range '10-1'"
+    );
+    assert_eq!(
+      error("icmp port 1"),
+      "invalid filter: 'port' can't be used with icmp"
+    );
+    assert_eq!(
+      error("ip host ::1"),
+      "invalid filter: '::1' can't be used with ip"
+    );
+    assert_eq!(error("(tcp"), "invalid filter: expected ')'");
+    assert_eq!(error("tcp udp"), "invalid filter: unexpected 'udp'");
+    assert_eq!(error("src"), "invalid filter: unexpected end of expression");
+  }
+}
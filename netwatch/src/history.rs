@@ -0,0 +1,218 @@
+use bytesize::ByteSize;
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::transfer::Transfer;
+
+/// The bytes transferred during a single interval.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sample {
+  pub incoming: u64,
+  pub outgoing: u64,
+  pub elapsed: Duration,
+}
+
+impl Sample {
+  /// Incoming and outgoing bytes per second during this interval.
+  pub fn rates(&self) -> (f64, f64) {
+    let seconds = self.elapsed.as_secs_f64();
+    if seconds == 0.0 {
+      return (0.0, 0.0);
+    }
+
+    (
+      self.incoming as f64 / seconds,
+      self.outgoing as f64 / seconds,
+    )
+  }
+
+  fn is_empty(&self) -> bool {
+    self.incoming == 0 && self.outgoing == 0
+  }
+}
+
+// History is a ring buffer of the most recent samples of a `Transfer`, taken each time it's reset.
+// Once full the oldest sample is dropped, so memory is bounded by `capacity`.
+#[derive(Debug, Clone)]
+pub struct History {
+  samples: VecDeque<Sample>,
+  capacity: usize,
+}
+
+impl History {
+  pub fn new(capacity: usize) -> History {
+    History {
+      samples: VecDeque::with_capacity(capacity),
+      capacity,
+    }
+  }
+
+  /// Records what `transfer` counted over the last `elapsed`, call this just before resetting it.
+  pub fn push(&mut self, transfer: &Transfer, elapsed: Duration) {
+    self.push_sample(Sample {
+      incoming: transfer.incoming(),
+      outgoing: transfer.outgoing(),
+      elapsed,
+    });
+  }
+
+  pub fn push_sample(&mut self, sample: Sample) {
+    if self.capacity == 0 {
+      return;
+    }
+
+    if self.samples.len() == self.capacity {
+      self.samples.pop_front();
+    }
+
+    self.samples.push_back(sample);
+  }
+
+  /// Samples from oldest to newest (e.g., to draw a sparkline).
+  pub fn samples(&self) -> impl DoubleEndedIterator<Item = &Sample> {
+    self.samples.iter()
+  }
+
+  /// The most recent sample.
+  pub fn last(&self) -> Option<&Sample> {
+    self.samples.back()
+  }
+
+  pub fn len(&self) -> usize {
+    self.samples.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.samples.is_empty()
+  }
+
+  /// Average incoming and outgoing bytes per second over the last `window`.
+  pub fn average(&self, window: Duration) -> (ByteSize, ByteSize) {
+    let (mut incoming, mut outgoing, mut elapsed) = (0, 0, Duration::default());
+    for sample in self.within(window) {
+      incoming += sample.incoming;
+      outgoing += sample.outgoing;
+      elapsed += sample.elapsed;
+    }
+
+    let (incoming, outgoing) = Sample {
+      incoming,
+      outgoing,
+      elapsed,
+    }
+    .rates();
+    (to_bytes(incoming), to_bytes(outgoing))
+  }
+
+  /// Highest incoming and outgoing bytes per second of any single interval in the last `window`.
+  pub fn peak(&self, window: Duration) -> (ByteSize, ByteSize) {
+    let (incoming, outgoing) = self
+      .within(window)
+      .map(Sample::rates)
+      .fold((0.0f64, 0.0f64), |(i, o), (si, so)| (i.max(si), o.max(so)));
+    (to_bytes(incoming), to_bytes(outgoing))
+  }
+
+  /// Exponentially weighted moving average of the incoming and outgoing bytes per second, where
+  /// a sample's weight decays by a factor of e every `window`. Samples are weighted by how long
+  /// they cover, so it's correct even when intervals aren't evenly spaced.
+  pub fn ewma(&self, window: Duration) -> (ByteSize, ByteSize) {
+    let window = window.as_secs_f64();
+    if window == 0.0 {
+      return self.last().map_or((ByteSize(0), ByteSize(0)), |sample| {
+        let (incoming, outgoing) = sample.rates();
+        (to_bytes(incoming), to_bytes(outgoing))
+      });
+    }
+
+    let mut average: Option<(f64, f64)> = None;
+    for sample in self.samples.iter() {
+      let (incoming, outgoing) = sample.rates();
+      average = Some(match average {
+        None => (incoming, outgoing),
+        Some((avg_in, avg_out)) => {
+          let alpha = 1.0 - (-sample.elapsed.as_secs_f64() / window).exp();
+          (
+            avg_in + alpha * (incoming - avg_in),
+            avg_out + alpha * (outgoing - avg_out),
+          )
+        }
+      });
+    }
+
+    let (incoming, outgoing) = average.unwrap_or_default();
+    (to_bytes(incoming), to_bytes(outgoing))
+  }
+
+  // The newest samples that fit in `window` (always at least the newest one, if any).
+  fn within(&self, window: Duration) -> impl Iterator<Item = &Sample> {
+    let mut total = Duration::default();
+    self.samples.iter().rev().take_while(move |sample| {
+      let include = total == Duration::default() || total + sample.elapsed <= window;
+      total += sample.elapsed;
+      include
+    })
+  }
+}
+
+// Histories keeps a `History` for each key (e.g., a PID or an interface name).
+// Keys that go quiet keep receiving empty samples so their graphs fall to zero, and are dropped
+// once their whole history is empty, so only recently active keys take up memory.
+#[derive(Debug, Clone)]
+pub struct Histories<K: Hash + Eq> {
+  inner: HashMap<K, History>,
+  capacity: usize,
+}
+
+impl<K: Hash + Eq> Histories<K> {
+  pub fn new(capacity: usize) -> Histories<K> {
+    Histories {
+      inner: HashMap::new(),
+      capacity,
+    }
+  }
+
+  /// Records one interval for every key. Keys missing from `transfers` get an empty sample.
+  pub fn tick<I: IntoIterator<Item = (K, Transfer)>>(&mut self, transfers: I, elapsed: Duration) {
+    let mut seen = HashMap::new();
+    for (key, transfer) in transfers {
+      seen
+        .entry(key)
+        .or_insert_with(Transfer::new)
+        .merge(&transfer);
+    }
+
+    for (key, history) in self.inner.iter_mut() {
+      if !seen.contains_key(key) {
+        history.push(&Transfer::new(), elapsed);
+      }
+    }
+
+    let capacity = self.capacity;
+    for (key, transfer) in seen {
+      self
+        .inner
+        .entry(key)
+        .or_insert_with(|| History::new(capacity))
+        .push(&transfer, elapsed);
+    }
+
+    self
+      .inner
+      .retain(|_, history| history.samples().any(|sample| !sample.is_empty()));
+  }
+
+  pub fn get(&self, key: &K) -> Option<&History> {
+    self.inner.get(key)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&K, &History)> {
+    self.inner.iter()
+  }
+}
+
+fn to_bytes(rate: f64) -> ByteSize {
+  ByteSize(rate.round() as u64)
+}
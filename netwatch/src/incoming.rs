@@ -1,8 +1,10 @@
 use pnet::datalink::NetworkInterface;
 use pnet::packet::arp::ArpPacket;
-use pnet::packet::ethernet::EthernetPacket;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
 
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
@@ -12,7 +14,22 @@ pub trait IsIncoming {
 
 impl<'a> IsIncoming for EthernetPacket<'a> {
     fn is_incoming(&self, interface: &NetworkInterface) -> bool {
-        Some(self.get_destination()) == interface.mac
+        // pnet reports interfaces without a hardware address (e.g., tunnels and loopback) as
+        // having an all-zero one, which every frame made up for them would match.
+        if let Some(mac) = interface.mac.filter(|mac| *mac != MacAddr::zero()) {
+            return self.get_destination() == mac;
+        }
+
+        // Interfaces without a hardware address only have IP addresses to go by.
+        match self.get_ethertype() {
+            EtherTypes::Ipv4 => {
+                Ipv4Packet::new(self.payload()).is_some_and(|ip| ip.is_incoming(interface))
+            }
+            EtherTypes::Ipv6 => {
+                Ipv6Packet::new(self.payload()).is_some_and(|ip| ip.is_incoming(interface))
+            }
+            _ => false,
+        }
     }
 }
 
@@ -1,5 +1,20 @@
+#[cfg(target_os = "linux")]
+#[path = "capture_linux.rs"]
+mod capture;
+pub mod cgroup;
 pub mod connection;
+pub mod error;
+pub mod filter;
+pub mod history;
 pub mod incoming;
+pub mod link;
 pub mod packet_monitor;
 pub mod port;
+pub mod reassembly;
+pub mod record;
+pub mod replay;
 pub mod transfer;
+pub mod tunnel;
+pub mod user;
+
+pub use error::{Error, Result};
@@ -0,0 +1,326 @@
+use pcap_file::DataLink;
+use pnet::datalink::NetworkInterface;
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::util::MacAddr;
+
+use std::borrow::Cow;
+#[cfg(target_os = "linux")]
+use std::fs;
+
+use crate::incoming::IsIncoming;
+
+// Hardware types (ARPHRD_* in linux/if_arp.h), as found in `/sys/class/net/{NAME}/type`, of the
+// interfaces that hand over bare IP packets.
+#[cfg(target_os = "linux")]
+const RAW_IP_HARDWARE_TYPES: [u16; 6] = [
+  512,   // PPP
+  519,   // RAWIP
+  768,   // TUNNEL (IP-in-IP)
+  769,   // TUNNEL6
+  776,   // SIT
+  65534, // NONE (TUN, WireGuard)
+];
+
+// Header lengths of Linux cooked captures (v1 and v2) and of BSD loopback captures.
+const SLL_HEADER_LEN: usize = 16;
+const SLL2_HEADER_LEN: usize = 20;
+const LOOPBACK_HEADER_LEN: usize = 4;
+
+// The SLL packet type of frames we sent (PACKET_OUTGOING in linux/if_packet.h).
+const PACKET_OUTGOING: u16 = 4;
+
+/// How the frames captured on an interface, or recorded in a file, start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkType {
+  Ethernet,
+  /// Bare IPv4 or IPv6 packets, e.g. from TUN, WireGuard and PPP interfaces.
+  RawIp,
+  /// Linux "cooked" captures, which is what capturing on the `any` pseudo-interface records.
+  LinuxSll,
+  LinuxSll2,
+  /// BSD loopback, where a 4-byte address family comes before the IP packet.
+  Loopback,
+}
+
+impl LinkType {
+  /// Works out what frames captured on `interface` look like. On Linux that's from its hardware
+  /// type, and elsewhere point-to-point interfaces without broadcast are assumed to be tunnels.
+  pub fn of(interface: &NetworkInterface) -> LinkType {
+    #[cfg(target_os = "linux")]
+    {
+      let path = format!("/sys/class/net/{}/type", interface.name);
+      if let Ok(Ok(hardware_type)) = fs::read_to_string(path).map(|s| s.trim().parse::<u16>()) {
+        return if RAW_IP_HARDWARE_TYPES.contains(&hardware_type) {
+          LinkType::RawIp
+        } else {
+          LinkType::Ethernet
+        };
+      }
+    }
+
+    if cfg!(target_os = "macos")
+      && interface.is_up()
+      && !interface.is_broadcast()
+      && !interface.is_loopback()
+      && interface.is_point_to_point()
+    {
+      LinkType::RawIp
+    } else {
+      LinkType::Ethernet
+    }
+  }
+
+  /// The link type of a capture file's frames, if they can be decoded.
+  pub fn from_data_link(data_link: DataLink) -> Option<LinkType> {
+    match data_link {
+      DataLink::ETHERNET => Some(LinkType::Ethernet),
+      DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => Some(LinkType::RawIp),
+      DataLink::LINUX_SLL => Some(LinkType::LinuxSll),
+      DataLink::LINUX_SLL2 => Some(LinkType::LinuxSll2),
+      DataLink::NULL | DataLink::LOOP => Some(LinkType::Loopback),
+      _ => None,
+    }
+  }
+
+  /// Turns `packet` into an Ethernet frame, which is what the handlers are given. Frames of other
+  /// link types get a made-up Ethernet header, with the interface's address on our side so the
+  /// frame's direction is kept, which makes them 14 bytes longer than the IP packet they carry.
+  pub fn to_ethernet<'a>(
+    self,
+    interface: &NetworkInterface,
+    packet: &'a [u8],
+  ) -> Option<Cow<'a, [u8]>> {
+    let (ethertype, is_incoming, payload) = match self {
+      LinkType::Ethernet => return Some(Cow::Borrowed(packet)),
+      LinkType::RawIp => (ip_ethertype(packet)?, None, packet),
+      LinkType::Loopback => {
+        // The address family is in the sender's byte order and its value for IPv6 differs between
+        // BSDs, so the IP version is more reliable.
+        let payload = packet.get(LOOPBACK_HEADER_LEN..)?;
+        (ip_ethertype(payload)?, None, payload)
+      }
+      LinkType::LinuxSll => {
+        let payload = packet.get(SLL_HEADER_LEN..)?;
+        let packet_type = u16::from_be_bytes([packet[0], packet[1]]);
+        let protocol = u16::from_be_bytes([packet[14], packet[15]]);
+        (
+          EtherType(protocol),
+          Some(packet_type != PACKET_OUTGOING),
+          payload,
+        )
+      }
+      LinkType::LinuxSll2 => {
+        let payload = packet.get(SLL2_HEADER_LEN..)?;
+        let protocol = u16::from_be_bytes([packet[0], packet[1]]);
+        let packet_type = u16::from(packet[10]);
+        (
+          EtherType(protocol),
+          Some(packet_type != PACKET_OUTGOING),
+          payload,
+        )
+      }
+    };
+
+    let is_incoming = is_incoming.unwrap_or_else(|| match ethertype {
+      EtherTypes::Ipv4 => Ipv4Packet::new(payload).is_some_and(|ip| ip.is_incoming(interface)),
+      _ => Ipv6Packet::new(payload).is_some_and(|ip| ip.is_incoming(interface)),
+    });
+
+    let mac = interface.mac.unwrap_or(MacAddr(0, 0, 0, 0, 0, 0));
+    let mut buffer = vec![0u8; EthernetPacket::minimum_packet_size() + payload.len()];
+    let mut frame = MutableEthernetPacket::new(&mut buffer[..]).unwrap();
+    if is_incoming {
+      frame.set_destination(mac);
+    } else {
+      frame.set_source(mac);
+    }
+    frame.set_ethertype(ethertype);
+    frame.set_payload(payload);
+
+    Some(Cow::Owned(buffer))
+  }
+}
+
+fn ip_ethertype(packet: &[u8]) -> Option<EtherType> {
+  match packet.first()? >> 4 {
+    4 => Some(EtherTypes::Ipv4),
+    6 => Some(EtherTypes::Ipv6),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pnet::packet::Packet;
+
+  const MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+
+  fn interface(mac: Option<MacAddr>) -> NetworkInterface {
+    NetworkInterface {
+      name: "tun0".into(),
+      description: String::new(),
+      index: 0,
+      mac,
+      ips: vec!["10.0.0.1/24".parse().unwrap()],
+      flags: 0,
+    }
+  }
+
+  fn ipv4(source: [u8; 4], destination: [u8; 4]) -> Vec<u8> {
+    let mut packet = vec![0x45, 0, 0, 24, 0, 0, 0, 0, 64, 17, 0, 0];
+    packet.extend_from_slice(&source);
+    packet.extend_from_slice(&destination);
+    packet.extend_from_slice(&[0xaa; 4]);
+    packet
+  }
+
+  fn ethernet(link_type: LinkType, interface: &NetworkInterface, packet: &[u8]) -> Vec<u8> {
+    link_type
+      .to_ethernet(interface, packet)
+      .unwrap()
+      .into_owned()
+  }
+
+  #[test]
+  fn ethernet_is_passed_through() {
+    let frame = [0xff; 60];
+    let converted = LinkType::Ethernet.to_ethernet(&interface(Some(MAC)), &frame);
+    assert!(matches!(converted, Some(Cow::Borrowed(_))));
+  }
+
+  #[test]
+  fn raw_ip() {
+    let interface = interface(Some(MAC));
+    let outgoing = ipv4([10, 0, 0, 1], [8, 8, 8, 8]);
+    let frame = ethernet(LinkType::RawIp, &interface, &outgoing);
+    let frame = EthernetPacket::new(&frame).unwrap();
+    assert_eq!(frame.get_source(), MAC);
+    assert_eq!(frame.get_destination(), MacAddr::zero());
+    assert_eq!(frame.get_ethertype(), EtherTypes::Ipv4);
+    assert_eq!(frame.payload(), &outgoing[..]);
+    assert!(!frame.is_incoming(&interface));
+
+    let incoming = ipv4([8, 8, 8, 8], [10, 0, 0, 1]);
+    let frame = ethernet(LinkType::RawIp, &interface, &incoming);
+    let frame = EthernetPacket::new(&frame).unwrap();
+    assert_eq!(frame.get_destination(), MAC);
+    assert!(frame.is_incoming(&interface));
+
+    let mut ipv6 = vec![0x60, 0, 0, 0, 0, 0, 59, 64];
+    ipv6.extend_from_slice(&[0; 32]);
+    let frame = ethernet(LinkType::RawIp, &interface, &ipv6);
+    assert_eq!(
+      EthernetPacket::new(&frame).unwrap().get_ethertype(),
+      EtherTypes::Ipv6
+    );
+
+    // Neither IPv4 nor IPv6.
+    assert_eq!(LinkType::RawIp.to_ethernet(&interface, &[0x50; 20]), None);
+    assert_eq!(LinkType::RawIp.to_ethernet(&interface, &[]), None);
+  }
+
+  #[test]
+  fn without_a_hardware_address() {
+    // TUN and WireGuard interfaces don't have one, which pnet reports as all zeroes.
+    for mac in [None, Some(MacAddr::zero())].iter() {
+      let interface = interface(*mac);
+      let outgoing = ethernet(
+        LinkType::RawIp,
+        &interface,
+        &ipv4([10, 0, 0, 1], [8, 8, 8, 8]),
+      );
+      assert!(!EthernetPacket::new(&outgoing)
+        .unwrap()
+        .is_incoming(&interface));
+
+      let incoming = ethernet(
+        LinkType::RawIp,
+        &interface,
+        &ipv4([8, 8, 8, 8], [10, 0, 0, 1]),
+      );
+      assert!(EthernetPacket::new(&incoming)
+        .unwrap()
+        .is_incoming(&interface));
+    }
+  }
+
+  #[test]
+  fn loopback() {
+    let interface = interface(None);
+    let mut packet = vec![2, 0, 0, 0];
+    packet.extend_from_slice(&ipv4([10, 0, 0, 1], [10, 0, 0, 1]));
+    let frame = ethernet(LinkType::Loopback, &interface, &packet);
+    let frame = EthernetPacket::new(&frame).unwrap();
+    assert_eq!(frame.get_ethertype(), EtherTypes::Ipv4);
+    assert_eq!(frame.payload(), &packet[4..]);
+
+    assert_eq!(LinkType::Loopback.to_ethernet(&interface, &[2, 0, 0]), None);
+  }
+
+  #[test]
+  fn linux_sll() {
+    let interface = interface(Some(MAC));
+    // Outgoing ARP, which is told apart by the packet type rather than by addresses.
+    let mut packet = vec![0, 4, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x08, 0x06];
+    packet.extend_from_slice(&[0x11; 28]);
+    let frame = ethernet(LinkType::LinuxSll, &interface, &packet);
+    let frame = EthernetPacket::new(&frame).unwrap();
+    assert_eq!(frame.get_source(), MAC);
+    assert_eq!(frame.get_ethertype(), EtherTypes::Arp);
+    assert_eq!(frame.payload(), &packet[SLL_HEADER_LEN..]);
+
+    // The same packet, but to us.
+    packet[1] = 0;
+    let frame = ethernet(LinkType::LinuxSll, &interface, &packet);
+    assert_eq!(EthernetPacket::new(&frame).unwrap().get_destination(), MAC);
+
+    assert_eq!(
+      LinkType::LinuxSll.to_ethernet(&interface, &packet[..15]),
+      None
+    );
+  }
+
+  #[test]
+  fn linux_sll2() {
+    let interface = interface(Some(MAC));
+    let mut packet = vec![
+      0x08, 0x00, 0, 0, 0, 0, 0, 2, 0, 1, 4, 6, 2, 0, 0, 0, 0, 1, 0, 0,
+    ];
+    packet.extend_from_slice(&ipv4([8, 8, 8, 8], [10, 0, 0, 1]));
+    let frame = ethernet(LinkType::LinuxSll2, &interface, &packet);
+    let frame = EthernetPacket::new(&frame).unwrap();
+    // Outgoing by its packet type, whatever its addresses say.
+    assert_eq!(frame.get_source(), MAC);
+    assert_eq!(frame.get_ethertype(), EtherTypes::Ipv4);
+    assert_eq!(frame.payload(), &packet[SLL2_HEADER_LEN..]);
+
+    assert_eq!(
+      LinkType::LinuxSll2.to_ethernet(&interface, &packet[..19]),
+      None
+    );
+  }
+
+  #[test]
+  fn data_links() {
+    assert_eq!(
+      LinkType::from_data_link(DataLink::ETHERNET),
+      Some(LinkType::Ethernet)
+    );
+    assert_eq!(
+      LinkType::from_data_link(DataLink::RAW),
+      Some(LinkType::RawIp)
+    );
+    assert_eq!(
+      LinkType::from_data_link(DataLink::LINUX_SLL2),
+      Some(LinkType::LinuxSll2)
+    );
+    assert_eq!(
+      LinkType::from_data_link(DataLink::NULL),
+      Some(LinkType::Loopback)
+    );
+    assert_eq!(LinkType::from_data_link(DataLink::IEEE802_11), None);
+  }
+}
@@ -1,73 +1,323 @@
 use pnet::datalink::Channel::Ethernet;
-use pnet::datalink::{self, NetworkInterface};
+use pnet::datalink::{self, DataLinkReceiver, NetworkInterface};
 use pnet::packet::arp::ArpPacket;
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
 use pnet::packet::icmp::{echo_reply, echo_request, IcmpPacket, IcmpTypes};
-use pnet::packet::icmpv6::Icmpv6Packet;
+use pnet::packet::icmpv6::{Icmpv6Packet, Icmpv6Types};
 use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
+use pnet::packet::ipv6::{FragmentPacket, Ipv6Packet};
 use pnet::packet::tcp::TcpPacket;
 use pnet::packet::udp::UdpPacket;
+use pnet::packet::vlan::VlanPacket;
 use pnet::packet::Packet;
-use pnet::util::MacAddr;
 
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::mem;
 use std::net::IpAddr;
-use std::thread;
+use std::panic;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(target_os = "linux")]
+use crate::capture::PacketReceiver;
+use crate::connection::{Flow, Protocol};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::incoming::IsIncoming;
+use crate::link::LinkType;
+use crate::reassembly::{FragmentKey, FragmentStats, Held, Reassembler, Reassembly};
+use crate::record::Recorder;
+use crate::replay::{Pace, Replay};
+use crate::tunnel::{self, Tunnel, ETHERTYPE_TRANSPARENT_ETHERNET};
+
+// How long the capture threads block waiting for a packet before checking if they should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+// How many frames can be queued up between the reader threads and the handlers, once it's full
+// the readers block (and the kernel starts dropping frames) until the handlers catch up.
+const CHANNEL_CAPACITY: usize = 1024;
+
+// Ethertypes of 802.1Q VLAN tags, and of the outer tag with 802.1ad (QinQ), both the standard one
+// and the one used before it was standardized.
+const ETHERTYPE_VLAN: EtherType = EtherType(0x8100);
+const ETHERTYPE_QINQ: EtherType = EtherType(0x88a8);
+const ETHERTYPE_QINQ_LEGACY: EtherType = EtherType(0x9100);
+
+// How many tunnels inside tunnels are looked into, so crafted packets can't nest forever.
+const MAX_TUNNEL_DEPTH: usize = 4;
 
 #[derive(Debug)]
 pub struct SrcDest(pub IpAddr, pub IpAddr);
 
+/// What a frame's bytes count towards. Every frame gets exactly one, so flows and buckets add up
+/// to everything that was captured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Attribution {
+  /// A TCP, UDP or ICMP echo flow, and whether the frame was incoming.
+  Flow(Flow, bool),
+  /// Traffic without a flow, which can't be matched to a socket.
+  Bucket(Bucket),
+}
+
+impl Attribution {
+  pub fn flow(&self) -> Option<&Flow> {
+    match self {
+      Attribution::Flow(flow, _) => Some(flow),
+      Attribution::Bucket(_) => None,
+    }
+  }
+}
+
+/// Traffic that isn't part of a flow, grouped by protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Bucket {
+  Arp,
+  /// ICMP other than our own echoes (e.g., unreachables, or being pinged).
+  Icmp,
+  /// ICMPv6 other than our own echoes, mostly neighbour discovery.
+  Icmpv6,
+  /// Any other IP protocol (e.g., IGMP, GRE or ESP), by number.
+  Ip(u8),
+  /// Any other ethertype.
+  Ethernet(u16),
+  /// Fragments of packets that were never reassembled.
+  Fragments,
+  /// Frames we couldn't parse.
+  Malformed,
+}
+
+impl Display for Bucket {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Bucket::Arp => f.pad("ARP"),
+      Bucket::Icmp => f.pad("ICMP"),
+      Bucket::Icmpv6 => f.pad("ICMPv6"),
+      Bucket::Ip(protocol) => f.pad(&format!("IP protocol {}", protocol)),
+      Bucket::Ethernet(ethertype) => f.pad(&format!("ethertype {:#06x}", ethertype)),
+      Bucket::Fragments => f.pad("fragments"),
+      Bucket::Malformed => f.pad("malformed"),
+    }
+  }
+}
+
+/// What went wrong with the frames handled so far. Problems are counted here rather than printed,
+/// so they can be shown alongside the capture (e.g., in a status bar).
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStats {
+  /// Frames, or parts of them (e.g., what a tunnel carried), that couldn't be parsed.
+  pub malformed: u64,
+  /// Frames that couldn't be written to the recording.
+  pub unrecorded: u64,
+  /// How many fragmented packets have been reassembled, and how many were given up on.
+  pub fragments: FragmentStats,
+  /// The most recent of the above.
+  pub last_problem: Option<String>,
+  /// Interfaces that stopped capturing while others carried on, and why.
+  pub failures: Vec<String>,
+}
+
+/// Why a capture thread finished.
+#[derive(Debug)]
+pub enum StopReason {
+  /// `CaptureHandle::stop` was called.
+  Stopped,
+  /// Receiving failed on every interface, this is the last of those errors.
+  Error(Error),
+}
+
+/// A handle to a running capture, returned from `PacketMonitor::start`.
+pub struct CaptureHandle {
+  should_stop: Arc<AtomicBool>,
+  thread: JoinHandle<(PacketMonitor, StopReason)>,
+}
+
+impl CaptureHandle {
+  /// Asks the capture thread to stop, and waits for it to finish.
+  pub fn stop(self) -> (PacketMonitor, StopReason) {
+    self.should_stop.store(true, Ordering::SeqCst);
+    self.join()
+  }
+
+  /// Waits for the capture thread to finish on its own, which only happens if every interface
+  /// fails.
+  pub fn join(self) -> (PacketMonitor, StopReason) {
+    match self.thread.join() {
+      Ok(result) => result,
+      // A handler panicked, so carry that panic on to the caller.
+      Err(e) => panic::resume_unwind(e),
+    }
+  }
+
+  /// Whether the capture thread has finished (i.e., `join` won't block).
+  pub fn is_finished(&self) -> bool {
+    self.thread.is_finished()
+  }
+}
+
+// Handlers set by the caller, which are called on the capture thread.
+type EthernetFrameHandler = Box<dyn FnMut(&NetworkInterface, &EthernetPacket, usize) + Send>;
+type VlanPacketHandler =
+  Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &VlanPacket) + Send>;
+type ArpPacketHandler =
+  Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &ArpPacket) + Send>;
+type Ipv4PacketHandler =
+  Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &Ipv4Packet) + Send>;
+type Ipv6PacketHandler =
+  Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &Ipv6Packet) + Send>;
+type Ipv6ExtensionHandler =
+  Box<dyn FnMut(&NetworkInterface, &Ipv6Packet, IpNextHeaderProtocol, &[u8]) + Send>;
+type TransportProtocolHandler =
+  Box<dyn FnMut(&NetworkInterface, &SrcDest, IpNextHeaderProtocol, &[u8]) + Send>;
+type TcpPacketHandler = Box<dyn FnMut(&NetworkInterface, &SrcDest, &TcpPacket) + Send>;
+type UdpPacketHandler = Box<dyn FnMut(&NetworkInterface, &SrcDest, &UdpPacket) + Send>;
+type IcmpPacketHandler = Box<dyn FnMut(&NetworkInterface, &SrcDest, &IcmpPacket) + Send>;
+type Icmpv6PacketHandler = Box<dyn FnMut(&NetworkInterface, &SrcDest, &Icmpv6Packet) + Send>;
+type TunnelHandler = Box<dyn FnMut(&NetworkInterface, &SrcDest, Tunnel, &[u8]) + Send>;
+type AttributedFrameHandler =
+  Box<dyn FnMut(&NetworkInterface, &EthernetPacket, usize, Option<u16>, &Attribution) + Send>;
+
 // TODO: document this
-// handle_ethernet_frame
+// handle_ethernet_frame (unwrapping any VLAN tags first)
 //  handle_arp_packet
-//  handle_ipv4_packet, handle_ipv6_packet
-//      handle_transport_protocol
+//  handle_ipv4_packet, handle_ipv6_packet (following its extension headers)
+//      handle_transport_protocol (and handle_tunnel, back up to the top, when decapsulating)
 //          handle_tcp_packet
 //          handle_udp_packet
 //          handle_icmp_packet
 //          handle_icmpv6_packet
+// Each of these sets the frame's `Attribution`, which is passed to the attributed frame handler
+// once the frame has been dispatched. Inside tunnels that carry Ethernet frames, handlers are given
+// the inner frame rather than the captured one.
 // TODO: use lifetimes rather than `'static + FnMut`
 pub struct PacketMonitor {
-  pub interface: NetworkInterface,
+  pub interfaces: Vec<NetworkInterface>,
+  // Index into `interfaces` of the interface the frame currently being dispatched came from.
+  current: usize,
+
+  handler_ethernet_frame: Option<EthernetFrameHandler>,
+  handler_vlan_packet: Option<VlanPacketHandler>,
 
-  handler_ethernet_frame: Option<Box<dyn FnMut(&NetworkInterface, &EthernetPacket)>>,
+  handler_arp_packet: Option<ArpPacketHandler>,
+  handler_ipv4_packet: Option<Ipv4PacketHandler>,
+  handler_ipv6_packet: Option<Ipv6PacketHandler>,
+  handler_ipv6_extension: Option<Ipv6ExtensionHandler>,
+  handler_transport_protocol: Option<TransportProtocolHandler>,
 
-  handler_arp_packet: Option<Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &ArpPacket)>>,
-  handler_ipv4_packet: Option<Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &Ipv4Packet)>>,
-  handler_ipv6_packet: Option<Box<dyn FnMut(&NetworkInterface, &EthernetPacket, &Ipv6Packet)>>,
-  handler_transport_protocol:
-    Option<Box<dyn FnMut(&NetworkInterface, &SrcDest, IpNextHeaderProtocol, &[u8])>>,
+  handler_tcp_packet: Option<TcpPacketHandler>,
+  handler_udp_packet: Option<UdpPacketHandler>,
+  handler_icmp_packet: Option<IcmpPacketHandler>,
+  handler_icmpv6_packet: Option<Icmpv6PacketHandler>,
+
+  handler_tunnel: Option<TunnelHandler>,
+
+  handler_attributed_frame: Option<AttributedFrameHandler>,
+
+  filter: Option<Filter>,
+  // Whether the current capture's filter is being applied by the kernel, rather than by us.
+  filter_in_kernel: bool,
+  recorder: Option<Recorder>,
+  decapsulate: bool,
+  // Shared with whoever asked for them, so they can be read while capturing.
+  stats: Arc<Mutex<CaptureStats>>,
+  // What the frame currently being dispatched counts towards, once that's known.
+  attribution: Option<Attribution>,
+  // The (innermost) VLAN ID of the frame currently being dispatched, if it's tagged.
+  vlan: Option<u16>,
+  // When the frame currently being dispatched was captured, and how long it was then (links
+  // without Ethernet headers get a made-up one, which shouldn't count).
+  timestamp: Duration,
+  len: usize,
+  // Fragments are held back until it's known what their packet is, and then released together.
+  reassembler: Reassembler<HeldFrame>,
+  held: bool,
+  released: Vec<HeldFrame>,
+  // The tunnels the packet currently being dispatched is inside, outermost first, and once inside
+  // one, which way the outermost packet was going.
+  tunnels: Vec<Tunnel>,
+  tunnel_incoming: Option<bool>,
+}
 
-  handler_tcp_packet: Option<Box<dyn FnMut(&NetworkInterface, &SrcDest, &TcpPacket)>>,
-  handler_udp_packet: Option<Box<dyn FnMut(&NetworkInterface, &SrcDest, &UdpPacket)>>,
-  handler_icmp_packet: Option<Box<dyn FnMut(&NetworkInterface, &SrcDest, &IcmpPacket)>>,
-  handler_icmpv6_packet: Option<Box<dyn FnMut(&NetworkInterface, &SrcDest, &Icmpv6Packet)>>,
+// The frame a packet is being dispatched from: the one that was captured, which is what the
+// reassembler holds on to, and the innermost one, which is what handlers are given. They're only
+// different inside tunnels that carry Ethernet frames (e.g., VXLAN).
+#[derive(Copy, Clone)]
+struct Frame<'a> {
+  captured: &'a EthernetPacket<'a>,
+  inner: &'a EthernetPacket<'a>,
+}
+
+// A fragment's frame, waiting on the rest of its packet.
+struct HeldFrame {
+  interface: usize,
+  timestamp: Duration,
+  frame: Vec<u8>,
+  len: usize,
+  vlan: Option<u16>,
+}
+
+impl Held for HeldFrame {
+  fn size(&self) -> usize {
+    self.frame.len()
+  }
 }
 
 impl PacketMonitor {
   pub fn new(interface: NetworkInterface) -> PacketMonitor {
+    PacketMonitor::with_interfaces(vec![interface])
+  }
+
+  /// Creates a monitor that captures on all of `interfaces` at once. Handlers are always passed
+  /// the interface each packet was captured on.
+  pub fn with_interfaces(interfaces: Vec<NetworkInterface>) -> PacketMonitor {
     PacketMonitor {
-      interface,
+      interfaces,
+      current: 0,
 
       handler_ethernet_frame: None,
+      handler_vlan_packet: None,
 
       handler_arp_packet: None,
       handler_ipv4_packet: None,
       handler_ipv6_packet: None,
+      handler_ipv6_extension: None,
       handler_transport_protocol: None,
 
       handler_icmp_packet: None,
       handler_icmpv6_packet: None,
       handler_tcp_packet: None,
       handler_udp_packet: None,
+
+      handler_tunnel: None,
+
+      handler_attributed_frame: None,
+
+      filter: None,
+      filter_in_kernel: false,
+      recorder: None,
+      decapsulate: false,
+      stats: Arc::new(Mutex::new(CaptureStats::default())),
+      attribution: None,
+      vlan: None,
+      timestamp: Duration::default(),
+      len: 0,
+      reassembler: Reassembler::new(),
+      held: false,
+      released: vec![],
+      tunnels: vec![],
+      tunnel_incoming: None,
     }
   }
 
   pub fn logger(interface: NetworkInterface) -> PacketMonitor {
     let mut packet_monitor = PacketMonitor::new(interface);
 
-    packet_monitor.set_handler_arp_packet(|iface, eth, arp| {
+    packet_monitor.set_handler_arp_packet(|iface, eth, _, arp| {
       println!(
         "[{}]: ARP packet: {}({}) > {}({}); operation: {:?}",
         iface.name,
@@ -81,7 +331,13 @@ impl PacketMonitor {
 
     packet_monitor.set_handler_icmp_packet(|iface, src_dest, icmp| match icmp.get_icmp_type() {
       IcmpTypes::EchoReply => {
-        let echo_reply_packet = echo_reply::EchoReplyPacket::new(icmp.packet()).unwrap();
+        let echo_reply_packet = match echo_reply::EchoReplyPacket::new(icmp.packet()) {
+          Some(echo_reply_packet) => echo_reply_packet,
+          None => {
+            eprintln!("[{}]: Malformed ICMP echo reply", iface.name);
+            return;
+          }
+        };
         println!(
           "[{}]: ICMP echo reply {} -> {} (seq={:?}, id={:?})",
           iface.name,
@@ -92,7 +348,13 @@ impl PacketMonitor {
         );
       }
       IcmpTypes::EchoRequest => {
-        let echo_request_packet = echo_request::EchoRequestPacket::new(icmp.packet()).unwrap();
+        let echo_request_packet = match echo_request::EchoRequestPacket::new(icmp.packet()) {
+          Some(echo_request_packet) => echo_request_packet,
+          None => {
+            eprintln!("[{}]: Malformed ICMP echo request", iface.name);
+            return;
+          }
+        };
         println!(
           "[{}]: ICMP echo request {} -> {} (seq={:?}, id={:?})",
           iface.name,
@@ -150,64 +412,220 @@ impl PacketMonitor {
 
   // ----------------------
 
-  // TODO: implement a `stop` function that turns this off
-  pub fn start(&mut self) {
-    // Create a channel to receive on
-    let (_, mut rx) = match datalink::channel(&self.interface, Default::default()) {
-      Ok(Ethernet(tx, rx)) => (tx, rx),
-      Ok(_) => panic!("packetdump: unhandled channel type: {}"),
-      Err(e) => panic!("packetdump: unable to create channel: {}", e),
-    };
+  /// Starts capturing on a background thread. The returned handle is used to stop the capture,
+  /// which hands back this `PacketMonitor` so it can be started again later.
+  ///
+  /// Each interface is read on its own thread, but every frame is handed to the handlers from a
+  /// single thread, so handlers never run concurrently.
+  pub fn start(mut self) -> Result<CaptureHandle> {
+    if self.interfaces.is_empty() {
+      return Err(no_interfaces());
+    }
+
+    let link_types = self.interfaces.iter().map(LinkType::of).collect::<Vec<_>>();
 
-    thread::spawn(move || {
-      loop {
-        let mut buf: [u8; 1600] = [0u8; 1600];
-        let mut fake_ethernet_frame = MutableEthernetPacket::new(&mut buf[..]).unwrap();
-        match rx.next() {
-          Ok(packet) => {
-            if cfg!(target_os = "macos")
-              && self.interface.is_up()
-              && !self.interface.is_broadcast()
-              && !self.interface.is_loopback()
-              && self.interface.is_point_to_point()
-            {
-              // Maybe is TUN interface
-              let version = Ipv4Packet::new(&packet).unwrap().get_version();
-              if version == 4 {
-                fake_ethernet_frame.set_destination(MacAddr(0, 0, 0, 0, 0, 0));
-                fake_ethernet_frame.set_source(MacAddr(0, 0, 0, 0, 0, 0));
-                fake_ethernet_frame.set_ethertype(EtherTypes::Ipv4);
-                fake_ethernet_frame.set_payload(&packet);
-                self.handle_ethernet_frame(&fake_ethernet_frame.to_immutable());
-                continue;
-              } else if version == 6 {
-                fake_ethernet_frame.set_destination(MacAddr(0, 0, 0, 0, 0, 0));
-                fake_ethernet_frame.set_source(MacAddr(0, 0, 0, 0, 0, 0));
-                fake_ethernet_frame.set_ethertype(EtherTypes::Ipv6);
-                fake_ethernet_frame.set_payload(&packet);
-                self.handle_ethernet_frame(&fake_ethernet_frame.to_immutable());
-                continue;
-              }
+    // If we have a filter try to have the kernel apply it. Filters are compiled for Ethernet
+    // frames, so other links are filtered once their frames have been turned into Ethernet ones.
+    let filters_in_kernel = link_types
+      .iter()
+      .map(|link_type| {
+        cfg!(target_os = "linux") && self.filter.is_some() && *link_type == LinkType::Ethernet
+      })
+      .collect::<Vec<_>>();
+
+    // Open every interface before spawning anything, so a failure leaves nothing running.
+    let receivers = self
+      .interfaces
+      .iter()
+      .zip(link_types.iter().zip(filters_in_kernel.iter()))
+      .map(|(interface, (link_type, filter_in_kernel))| {
+        self.open(interface, *link_type, *filter_in_kernel)
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    let should_stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    let readers = receivers
+      .into_iter()
+      .enumerate()
+      .map(|(index, rx)| spawn_reader(index, rx, tx.clone(), should_stop.clone()))
+      .collect::<Vec<_>>();
+    drop(tx);
+
+    let thread_should_stop = should_stop.clone();
+    let thread = thread::spawn(move || {
+      let mut running = readers.len();
+      let reason = loop {
+        if thread_should_stop.load(Ordering::SeqCst) {
+          break StopReason::Stopped;
+        }
+
+        match rx.recv_timeout(READ_TIMEOUT) {
+          Ok(Captured::Frame(index, timestamp, packet)) => {
+            self.current = index;
+            self.filter_in_kernel = filters_in_kernel[index];
+            self.handle_packet(timestamp, link_types[index], &packet);
+          }
+          // Keep going while there are other interfaces left (e.g., a VPN going away shouldn't
+          // end the whole capture), and only report the last failure.
+          Ok(Captured::Failed(index, e)) => {
+            running -= 1;
+            if running == 0 {
+              break StopReason::Error(e);
             }
-            self.handle_ethernet_frame(&EthernetPacket::new(packet).unwrap());
+
+            let failure = format!("[{}]: {}", self.interfaces[index].name, e);
+            self.stats.lock().unwrap().failures.push(failure);
           }
-          Err(e) => panic!("packetdump: unable to receive packet: {}", e),
+          // Fragments are otherwise only given up on when another frame arrives.
+          Err(RecvTimeoutError::Timeout) => self.expire_fragments(now()),
+          Err(RecvTimeoutError::Disconnected) => break StopReason::Stopped,
         }
+      };
+
+      // No more fragments are coming, so don't leave any behind uncounted and unrecorded.
+      self.expire_fragments(Duration::MAX);
+
+      // Dropping the receiver unblocks any reader that's waiting on a full channel.
+      thread_should_stop.store(true, Ordering::SeqCst);
+      drop(rx);
+      for reader in readers {
+        let _ = reader.join();
       }
+
+      (self, reason)
     });
+
+    Ok(CaptureHandle {
+      should_stop,
+      thread,
+    })
+  }
+
+  // Creates a channel to receive on from `interface`, with the filter attached to it if it's to
+  // be applied by the kernel. On Linux, Ethernet links use our own socket, which also puts back
+  // the VLAN tags the kernel takes out of frames.
+  fn open(
+    &self,
+    interface: &NetworkInterface,
+    link_type: LinkType,
+    filter_in_kernel: bool,
+  ) -> Result<Box<dyn DataLinkReceiver>> {
+    #[cfg(target_os = "linux")]
+    {
+      if link_type == LinkType::Ethernet {
+        let filter = self.filter.as_ref().filter(|_| filter_in_kernel);
+        return Ok(Box::new(
+          PacketReceiver::open(interface, filter, READ_TIMEOUT).map_err(Error::capture)?,
+        ));
+      }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (link_type, filter_in_kernel);
+
+    // Use a read timeout so the reader thread can periodically check if it should stop.
+    let config = datalink::Config {
+      read_timeout: Some(READ_TIMEOUT),
+      ..Default::default()
+    };
+
+    match datalink::channel(interface, config) {
+      Ok(Ethernet(_, rx)) => Ok(rx),
+      Ok(_) => Err(Error::Capture(io::Error::other("unhandled channel type"))),
+      Err(e) => Err(Error::capture(e)),
+    }
+  }
+
+  /// Feeds every frame in a pcap or pcapng file through the handlers, blocking until the whole
+  /// file has been read. `self.interfaces` are still used to decide which packets are incoming,
+  /// so they should describe the interfaces the capture was taken on. Frames are matched to them
+  /// by name when the file records one, and otherwise attributed to the first interface.
+  pub fn replay<P: AsRef<Path>>(&mut self, path: P, pace: Pace) -> Result<()> {
+    if self.interfaces.is_empty() {
+      return Err(no_interfaces());
+    }
+
+    let mut replay = Replay::open(path)?;
+    self.filter_in_kernel = false;
+
+    let started = Instant::now();
+    let mut first_timestamp = None;
+    while let Some(frame) = replay.next_frame() {
+      let frame = frame?;
+
+      if pace == Pace::Original {
+        let first_timestamp = *first_timestamp.get_or_insert(frame.timestamp);
+        let offset = frame
+          .timestamp
+          .checked_sub(first_timestamp)
+          .unwrap_or_default();
+        if let Some(delay) = offset.checked_sub(started.elapsed()) {
+          thread::sleep(delay);
+        }
+      }
+
+      self.current = frame
+        .interface
+        .and_then(|name| self.interfaces.iter().position(|i| i.name == name))
+        .unwrap_or(0);
+
+      self.handle_packet(frame.timestamp, frame.link_type, &frame.data);
+    }
+    self.expire_fragments(Duration::MAX);
+
+    Ok(())
+  }
+
+  /// Only handle frames that match `filter`. This takes effect the next time the monitor is
+  /// started or replayed.
+  pub fn set_filter(&mut self, filter: Filter) {
+    self.filter = Some(filter);
+  }
+
+  /// Writes every frame to a pcapng file as it's handled.
+  pub fn set_recorder(&mut self, recorder: Recorder) {
+    self.recorder = Some(recorder);
+  }
+
+  /// Whether to look inside GRE, VXLAN, Geneve and IP-in-IP tunnels, so their traffic counts
+  /// towards the flows inside them rather than the tunnel's. Off by default.
+  pub fn set_decapsulate(&mut self, decapsulate: bool) {
+    self.decapsulate = decapsulate;
+  }
+
+  /// Problems with the frames handled so far. This is updated while capturing, and is the same
+  /// across restarts.
+  pub fn stats(&self) -> Arc<Mutex<CaptureStats>> {
+    self.stats.clone()
   }
 
   // ----------------------
 
-  pub fn set_handler_ethernet_frame<H: 'static + FnMut(&NetworkInterface, &EthernetPacket)>(
+  /// Called for every frame with its length as captured, which for links without Ethernet
+  /// headers (e.g., tunnels) is shorter than the frame, since that has a made-up header.
+  pub fn set_handler_ethernet_frame<
+    H: 'static + Send + FnMut(&NetworkInterface, &EthernetPacket, usize),
+  >(
     &mut self,
     handler: H,
   ) {
     self.handler_ethernet_frame = Some(Box::new(handler));
   }
 
+  /// Called for each VLAN tag, so twice for QinQ frames (outer tag first). Like the ARP, IPv4 and
+  /// IPv6 handlers, this is given the innermost Ethernet frame the packet is in and the tunnels
+  /// it's inside, outermost first, which are only looked into when decapsulating.
+  pub fn set_handler_vlan_packet<
+    H: 'static + Send + FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &VlanPacket),
+  >(
+    &mut self,
+    handler: H,
+  ) {
+    self.handler_vlan_packet = Some(Box::new(handler));
+  }
+
   pub fn set_handler_arp_packet<
-    H: 'static + FnMut(&NetworkInterface, &EthernetPacket, &ArpPacket),
+    H: 'static + Send + FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &ArpPacket),
   >(
     &mut self,
     handler: H,
@@ -216,7 +634,7 @@ impl PacketMonitor {
   }
 
   pub fn set_handler_ipv4_packet<
-    H: 'static + FnMut(&NetworkInterface, &EthernetPacket, &Ipv4Packet),
+    H: 'static + Send + FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &Ipv4Packet),
   >(
     &mut self,
     handler: H,
@@ -224,15 +642,24 @@ impl PacketMonitor {
     self.handler_ipv4_packet = Some(Box::new(handler));
   }
   pub fn set_handler_ipv6_packet<
-    H: 'static + FnMut(&NetworkInterface, &EthernetPacket, &Ipv6Packet),
+    H: 'static + Send + FnMut(&NetworkInterface, &EthernetPacket, &[Tunnel], &Ipv6Packet),
   >(
     &mut self,
     handler: H,
   ) {
     self.handler_ipv6_packet = Some(Box::new(handler));
   }
+  /// Called for each IPv6 extension header, in order, with its type and its bytes.
+  pub fn set_handler_ipv6_extension<
+    H: 'static + Send + FnMut(&NetworkInterface, &Ipv6Packet, IpNextHeaderProtocol, &[u8]),
+  >(
+    &mut self,
+    handler: H,
+  ) {
+    self.handler_ipv6_extension = Some(Box::new(handler));
+  }
   pub fn set_handler_transport_protocol<
-    H: 'static + FnMut(&NetworkInterface, &SrcDest, IpNextHeaderProtocol, &[u8]),
+    H: 'static + Send + FnMut(&NetworkInterface, &SrcDest, IpNextHeaderProtocol, &[u8]),
   >(
     &mut self,
     handler: H,
@@ -240,26 +667,32 @@ impl PacketMonitor {
     self.handler_transport_protocol = Some(Box::new(handler));
   }
 
-  pub fn set_handler_tcp_packet<H: 'static + FnMut(&NetworkInterface, &SrcDest, &TcpPacket)>(
+  pub fn set_handler_tcp_packet<
+    H: 'static + Send + FnMut(&NetworkInterface, &SrcDest, &TcpPacket),
+  >(
     &mut self,
     handler: H,
   ) {
     self.handler_tcp_packet = Some(Box::new(handler));
   }
-  pub fn set_handler_udp_packet<H: 'static + FnMut(&NetworkInterface, &SrcDest, &UdpPacket)>(
+  pub fn set_handler_udp_packet<
+    H: 'static + Send + FnMut(&NetworkInterface, &SrcDest, &UdpPacket),
+  >(
     &mut self,
     handler: H,
   ) {
     self.handler_udp_packet = Some(Box::new(handler));
   }
-  pub fn set_handler_icmp_packet<H: 'static + FnMut(&NetworkInterface, &SrcDest, &IcmpPacket)>(
+  pub fn set_handler_icmp_packet<
+    H: 'static + Send + FnMut(&NetworkInterface, &SrcDest, &IcmpPacket),
+  >(
     &mut self,
     handler: H,
   ) {
     self.handler_icmp_packet = Some(Box::new(handler));
   }
   pub fn set_handler_icmpv6_packet<
-    H: 'static + FnMut(&NetworkInterface, &SrcDest, &Icmpv6Packet),
+    H: 'static + Send + FnMut(&NetworkInterface, &SrcDest, &Icmpv6Packet),
   >(
     &mut self,
     handler: H,
@@ -267,109 +700,400 @@ impl PacketMonitor {
     self.handler_icmpv6_packet = Some(Box::new(handler));
   }
 
+  /// Called for each tunnel a packet is decapsulated from, with the outer packet's addresses and
+  /// what the tunnel carries (an Ethernet frame or an IP packet). The handlers for the outer
+  /// headers have been called by then, and the ones for the inner headers are called after.
+  pub fn set_handler_tunnel<
+    H: 'static + Send + FnMut(&NetworkInterface, &SrcDest, Tunnel, &[u8]),
+  >(
+    &mut self,
+    handler: H,
+  ) {
+    self.handler_tunnel = Some(Box::new(handler));
+  }
+
+  /// Called once every frame has been dispatched with its length as captured (like the Ethernet
+  /// frame handler), its (innermost) VLAN ID, if it's tagged, and what its bytes count towards,
+  /// which is what totals per process should be built from.
+  pub fn set_handler_attributed_frame<
+    H: 'static + Send + FnMut(&NetworkInterface, &EthernetPacket, usize, Option<u16>, &Attribution),
+  >(
+    &mut self,
+    handler: H,
+  ) {
+    self.handler_attributed_frame = Some(Box::new(handler));
+  }
+
   // ----------------------
 
+  fn handle_packet(&mut self, timestamp: Duration, link_type: LinkType, packet: &[u8]) {
+    let frame = link_type.to_ethernet(&self.interfaces[self.current], packet);
+    if let Some(ethernet) = frame.as_ref().and_then(|frame| EthernetPacket::new(frame)) {
+      self.handle_frame(timestamp, &ethernet, packet.len());
+    } else {
+      self.malformed(format!("{:?} Frame", link_type));
+    }
+  }
+
+  // Entry point for every captured frame, `timestamp` is the time since the UNIX epoch and `len`
+  // how long it was as captured.
+  fn handle_frame(&mut self, timestamp: Duration, ethernet: &EthernetPacket, len: usize) {
+    if !self.filter_in_kernel {
+      if let Some(filter) = self.filter.as_ref() {
+        if !filter.matches(ethernet.packet()) {
+          return;
+        }
+      }
+    }
+
+    self.attribution = None;
+    self.vlan = None;
+    self.timestamp = timestamp;
+    self.len = len;
+    self.held = false;
+    self.tunnels.clear();
+    self.tunnel_incoming = None;
+    self.handle_ethernet_frame(ethernet);
+    let attribution = self
+      .attribution
+      .take()
+      .unwrap_or(Attribution::Bucket(Bucket::Malformed));
+
+    // A held fragment is attributed along with the rest of its packet once that's complete (which
+    // may be now), or once the packet is given up on.
+    let released = mem::take(&mut self.released);
+    let abandoned = self.reassembler.abandoned(timestamp);
+    if let Some(handler) = self.handler_attributed_frame.as_mut() {
+      if !self.held {
+        handler(
+          &self.interfaces[self.current],
+          ethernet,
+          len,
+          self.vlan,
+          &attribution,
+        );
+      }
+    }
+    if self.held || !abandoned.is_empty() {
+      self.stats.lock().unwrap().fragments = self.reassembler.stats();
+    }
+    self.handle_held(&released, &attribution);
+    self.handle_held(&abandoned, &Attribution::Bucket(Bucket::Fragments));
+
+    if !self.held {
+      self.record(self.current, timestamp, ethernet.packet(), &attribution);
+    }
+  }
+
+  // Writes a frame to the recorder, if there is one, annotated with the flow it's attributed to.
+  fn record(&mut self, interface: usize, timestamp: Duration, frame: &[u8], attribution: &Attribution) {
+    if let Some(recorder) = self.recorder.as_mut() {
+      let interface = &self.interfaces[interface];
+      if let Err(e) = recorder.record(interface, timestamp, frame, attribution.flow()) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.unrecorded += 1;
+        stats.last_problem = Some(format!(
+          "[{}]: Failed to record frame: {}",
+          interface.name, e
+        ));
+      }
+    }
+  }
+
+  // Gives up on fragments that have timed out by `now` without another frame arriving.
+  fn expire_fragments(&mut self, now: Duration) {
+    let abandoned = self.reassembler.abandoned(now);
+    if !abandoned.is_empty() {
+      self.stats.lock().unwrap().fragments = self.reassembler.stats();
+      self.handle_held(&abandoned, &Attribution::Bucket(Bucket::Fragments));
+    }
+  }
+
+  // Hands frames that were held back to the attributed frame handler, and records them now that
+  // it's known what they're part of.
+  fn handle_held(&mut self, held: &[HeldFrame], attribution: &Attribution) {
+    for held in held {
+      if let Some(handler) = self.handler_attributed_frame.as_mut() {
+        if let Some(frame) = EthernetPacket::new(&held.frame) {
+          handler(
+            &self.interfaces[held.interface],
+            &frame,
+            held.len,
+            held.vlan,
+            attribution,
+          );
+        }
+      }
+      self.record(held.interface, held.timestamp, &held.frame, attribution);
+    }
+  }
+
   fn handle_ethernet_frame(&mut self, ethernet: &EthernetPacket) {
     if let Some(handler) = self.handler_ethernet_frame.as_mut() {
-      handler(&self.interface, ethernet);
-    }
-
-    let interface_name = &self.interface.name.clone()[..];
-    match ethernet.get_ethertype() {
-      EtherTypes::Ipv4 => self.handle_ipv4_packet(ethernet),
-      EtherTypes::Ipv6 => self.handle_ipv6_packet(ethernet),
-      EtherTypes::Arp => self.handle_arp_packet(ethernet),
-      _ => eprintln!(
-        "[{}]: Unknown packet: {} > {}; ethertype: {:?} length: {}",
-        interface_name,
-        ethernet.get_source(),
-        ethernet.get_destination(),
-        ethernet.get_ethertype(),
-        ethernet.packet().len()
-      ),
+      handler(&self.interfaces[self.current], ethernet, self.len);
+    }
+
+    let frame = Frame {
+      captured: ethernet,
+      inner: ethernet,
+    };
+    self.handle_ethertype(frame, ethernet.get_ethertype(), ethernet.payload());
+  }
+
+  // Dispatches `payload`, which is inside `frame`, by its ethertype.
+  fn handle_ethertype(&mut self, frame: Frame, mut ethertype: EtherType, mut payload: &[u8]) {
+    // Unwrap any VLAN tags, with QinQ there's an outer one for the provider and an inner one for
+    // the customer. The innermost ID is the one kept, since that's the network the host is on
+    // (tags inside tunnels belong to someone else's network).
+    while ethertype == ETHERTYPE_VLAN
+      || ethertype == ETHERTYPE_QINQ
+      || ethertype == ETHERTYPE_QINQ_LEGACY
+    {
+      let header = match VlanPacket::new(payload) {
+        Some(header) => header,
+        None => {
+          self.malformed("VLAN Tag");
+          return;
+        }
+      };
+      if let Some(handler) = self.handler_vlan_packet.as_mut() {
+        handler(
+          &self.interfaces[self.current],
+          frame.inner,
+          &self.tunnels,
+          &header,
+        );
+      }
+
+      if self.tunnels.is_empty() {
+        self.vlan = Some(header.get_vlan_identifier());
+      }
+      ethertype = header.get_ethertype();
+      payload = &payload[VlanPacket::minimum_packet_size()..];
+    }
+
+    match ethertype {
+      EtherTypes::Ipv4 => self.handle_ipv4_packet(frame, payload),
+      EtherTypes::Ipv6 => self.handle_ipv6_packet(frame, payload),
+      EtherTypes::Arp => self.handle_arp_packet(frame, payload),
+      ethertype => self.attribution = Some(Attribution::Bucket(Bucket::Ethernet(ethertype.0))),
     }
   }
 
   // ---------------------------
 
-  fn handle_arp_packet(&mut self, ethernet: &EthernetPacket) {
-    let header = ArpPacket::new(ethernet.payload());
+  fn handle_arp_packet(&mut self, frame: Frame, packet: &[u8]) {
+    let header = ArpPacket::new(packet);
     if let Some(header) = header {
+      self.attribution = Some(Attribution::Bucket(Bucket::Arp));
       if let Some(handler) = self.handler_arp_packet.as_mut() {
-        handler(&self.interface, ethernet, &header);
+        handler(
+          &self.interfaces[self.current],
+          frame.inner,
+          &self.tunnels,
+          &header,
+        );
       }
     } else {
-      eprintln!("[{}]: Malformed ARP Packet", self.interface.name);
+      self.malformed("ARP Packet");
     }
   }
 
-  fn handle_ipv4_packet(&mut self, ethernet: &EthernetPacket) {
-    let header = Ipv4Packet::new(ethernet.payload());
+  fn handle_ipv4_packet(&mut self, frame: Frame, packet: &[u8]) {
+    let header = Ipv4Packet::new(packet);
     if let Some(header) = header {
       if let Some(handler) = self.handler_ipv4_packet.as_mut() {
-        handler(&self.interface, ethernet, &header);
+        handler(
+          &self.interfaces[self.current],
+          frame.inner,
+          &self.tunnels,
+          &header,
+        );
       }
 
-      self.handle_transport_protocol(
-        SrcDest(
-          IpAddr::V4(header.get_source()),
-          IpAddr::V4(header.get_destination()),
-        ),
-        header.get_next_level_protocol(),
-        header.payload(),
+      let src_dest = SrcDest(
+        IpAddr::V4(header.get_source()),
+        IpAddr::V4(header.get_destination()),
       );
+      let protocol = header.get_next_level_protocol();
+      let offset = header.get_fragment_offset() as usize * 8;
+      let more = header.get_flags() & Ipv4Flags::MoreFragments != 0;
+      if offset == 0 && !more {
+        self.handle_transport_protocol(frame, src_dest, protocol, header.payload());
+        return;
+      }
+
+      let key = FragmentKey {
+        source: src_dest.0,
+        destination: src_dest.1,
+        protocol: protocol.0,
+        id: u32::from(header.get_identification()),
+      };
+      if let Some(payload) = self.reassemble(frame, key, offset, more, header.payload()) {
+        self.handle_transport_protocol(frame, src_dest, protocol, &payload);
+      }
     } else {
-      eprintln!("[{}]: Malformed IPv4 Packet", self.interface.name);
+      self.malformed("IPv4 Packet");
     }
   }
 
-  fn handle_ipv6_packet(&mut self, ethernet: &EthernetPacket) {
-    let header = Ipv6Packet::new(ethernet.payload());
+  fn handle_ipv6_packet(&mut self, frame: Frame, packet: &[u8]) {
+    let header = Ipv6Packet::new(packet);
     if let Some(header) = header {
       if let Some(handler) = self.handler_ipv6_packet.as_mut() {
-        handler(&self.interface, ethernet, &header);
+        handler(
+          &self.interfaces[self.current],
+          frame.inner,
+          &self.tunnels,
+          &header,
+        );
       }
 
-      self.handle_transport_protocol(
-        SrcDest(
-          IpAddr::V6(header.get_source()),
-          IpAddr::V6(header.get_destination()),
-        ),
-        header.get_next_header(),
-        header.payload(),
+      let src_dest = SrcDest(
+        IpAddr::V6(header.get_source()),
+        IpAddr::V6(header.get_destination()),
       );
+      let (protocol, payload) =
+        self.handle_ipv6_extensions(&header, header.get_next_header(), header.payload());
+      let fragment = match FragmentPacket::new(payload) {
+        Some(fragment) if protocol == IpNextHeaderProtocols::Ipv6Frag => fragment,
+        _ => {
+          self.handle_transport_protocol(frame, src_dest, protocol, payload);
+          return;
+        }
+      };
+
+      // What comes after the fragment header is only complete once it's been reassembled, and
+      // can start with more extension headers.
+      let key = FragmentKey {
+        source: src_dest.0,
+        destination: src_dest.1,
+        protocol: fragment.get_next_header().0,
+        id: fragment.get_id(),
+      };
+      let data = &payload[FragmentPacket::minimum_packet_size()..];
+      let offset = fragment.get_fragment_offset() as usize;
+      let more = !fragment.is_last_fragment();
+      if let Some(payload) = self.reassemble(frame, key, offset, more, data) {
+        let (protocol, payload) =
+          self.handle_ipv6_extensions(&header, fragment.get_next_header(), &payload);
+        self.handle_transport_protocol(frame, src_dest, protocol, payload);
+      }
     } else {
-      eprintln!("[{}]: Malformed IPv6 Packet", self.interface.name);
+      self.malformed("IPv6 Packet");
+    }
+  }
+
+  // Follows the extension headers in `payload` to the upper-layer protocol, and returns it along
+  // with its payload. Stops early at anything that can't be followed (e.g., ESP, which is
+  // encrypted), or at a fragment header, since the rest has to be reassembled first.
+  fn handle_ipv6_extensions<'p>(
+    &mut self,
+    header: &Ipv6Packet,
+    mut protocol: IpNextHeaderProtocol,
+    mut payload: &'p [u8],
+  ) -> (IpNextHeaderProtocol, &'p [u8]) {
+    loop {
+      let length = match protocol {
+        IpNextHeaderProtocols::Hopopt
+        | IpNextHeaderProtocols::Ipv6Route
+        | IpNextHeaderProtocols::Ipv6Opts
+        | IpNextHeaderProtocols::MobilityHeader
+        | IpNextHeaderProtocols::Hip
+        | IpNextHeaderProtocols::Shim6 => payload.get(1).map(|length| (*length as usize + 1) * 8),
+        IpNextHeaderProtocols::Ah => payload.get(1).map(|length| (*length as usize + 2) * 4),
+        IpNextHeaderProtocols::Ipv6Frag => Some(FragmentPacket::minimum_packet_size()),
+        _ => None,
+      };
+      let length = match length {
+        Some(length) if length <= payload.len() => length,
+        _ => return (protocol, payload),
+      };
+
+      if let Some(handler) = self.handler_ipv6_extension.as_mut() {
+        handler(
+          &self.interfaces[self.current],
+          header,
+          protocol,
+          &payload[..length],
+        );
+      }
+
+      if protocol == IpNextHeaderProtocols::Ipv6Frag {
+        return (protocol, payload);
+      }
+
+      protocol = IpNextHeaderProtocol(payload[0]);
+      payload = &payload[length..];
+    }
+  }
+
+  // Holds on to a fragment's frame until the rest of its packet arrives, and returns the packet's
+  // whole payload once it has.
+  fn reassemble(
+    &mut self,
+    frame: Frame,
+    key: FragmentKey,
+    offset: usize,
+    more: bool,
+    data: &[u8],
+  ) -> Option<Vec<u8>> {
+    let held = HeldFrame {
+      interface: self.current,
+      timestamp: self.timestamp,
+      frame: frame.captured.packet().to_vec(),
+      len: self.len,
+      vlan: self.vlan,
+    };
+    self.held = true;
+
+    match self
+      .reassembler
+      .insert(key, self.timestamp, offset, more, data, held)
+    {
+      Reassembly::Incomplete => None,
+      Reassembly::Complete(payload, held) => {
+        self.released.extend(held);
+        Some(payload)
+      }
+      Reassembly::Dropped(held) => {
+        self.released.extend(held);
+        self.attribution = Some(Attribution::Bucket(Bucket::Fragments));
+        None
+      }
     }
   }
 
   fn handle_transport_protocol(
     &mut self,
+    frame: Frame,
     src_dest: SrcDest,
     protocol: IpNextHeaderProtocol,
     packet: &[u8],
   ) {
     if let Some(handler) = self.handler_transport_protocol.as_mut() {
-      handler(&self.interface, &src_dest, protocol, packet);
+      handler(&self.interfaces[self.current], &src_dest, protocol, packet);
+    }
+
+    if self.decapsulate && self.tunnels.len() < MAX_TUNNEL_DEPTH {
+      let tunnel = match protocol {
+        IpNextHeaderProtocols::Ipv4 => Some((Tunnel::IpInIp, EtherTypes::Ipv4, packet)),
+        IpNextHeaderProtocols::Ipv6 => Some((Tunnel::IpInIp, EtherTypes::Ipv6, packet)),
+        IpNextHeaderProtocols::Gre => tunnel::gre(packet),
+        _ => None,
+      };
+      if let Some((tunnel, ethertype, payload)) = tunnel {
+        self.handle_tunnel(frame, &src_dest, tunnel, ethertype, payload);
+        return;
+      }
     }
 
     match protocol {
-      IpNextHeaderProtocols::Udp => self.handle_udp_packet(src_dest, packet),
+      IpNextHeaderProtocols::Udp => self.handle_udp_packet(frame, src_dest, packet),
       IpNextHeaderProtocols::Tcp => self.handle_tcp_packet(src_dest, packet),
       IpNextHeaderProtocols::Icmp => self.handle_icmp_packet(src_dest, packet),
       IpNextHeaderProtocols::Icmpv6 => self.handle_icmpv6_packet(src_dest, packet),
-      _ => eprintln!(
-        "[{}]: Unknown {} packet: {} > {}; protocol: {:?} length: {}",
-        self.interface.name,
-        match src_dest.0 {
-          IpAddr::V4(..) => "IPv4",
-          _ => "IPv6",
-        },
-        src_dest.0,
-        src_dest.1,
-        protocol,
-        packet.len()
-      ),
+      _ => self.attribution = Some(Attribution::Bucket(Bucket::Ip(protocol.0))),
     }
   }
 
@@ -378,45 +1102,220 @@ impl PacketMonitor {
   fn handle_icmp_packet(&mut self, src_dest: SrcDest, packet: &[u8]) {
     let icmp_packet = IcmpPacket::new(packet);
     if let Some(icmp_packet) = icmp_packet {
+      let is_request = match icmp_packet.get_icmp_type() {
+        IcmpTypes::EchoRequest => Some(true),
+        IcmpTypes::EchoReply => Some(false),
+        _ => None,
+      };
+      self.attribution =
+        Some(self.echo_attribution(Protocol::Icmp, &src_dest, is_request, icmp_packet.payload()));
+
       if let Some(handler) = self.handler_icmp_packet.as_mut() {
-        handler(&self.interface, &src_dest, &icmp_packet);
+        handler(&self.interfaces[self.current], &src_dest, &icmp_packet);
       }
     } else {
-      eprintln!("[{}]: Malformed ICMP Packet", self.interface.name);
+      self.malformed("ICMP Packet");
     }
   }
 
   fn handle_icmpv6_packet(&mut self, src_dest: SrcDest, packet: &[u8]) {
     let icmpv6_packet = Icmpv6Packet::new(packet);
     if let Some(icmpv6_packet) = icmpv6_packet {
+      let is_request = match icmpv6_packet.get_icmpv6_type() {
+        Icmpv6Types::EchoRequest => Some(true),
+        Icmpv6Types::EchoReply => Some(false),
+        _ => None,
+      };
+      self.attribution = Some(self.echo_attribution(
+        Protocol::Icmpv6,
+        &src_dest,
+        is_request,
+        icmpv6_packet.payload(),
+      ));
+
       if let Some(handler) = self.handler_icmpv6_packet.as_mut() {
-        handler(&self.interface, &src_dest, &icmpv6_packet);
+        handler(&self.interfaces[self.current], &src_dest, &icmpv6_packet);
       }
     } else {
-      eprintln!("[{}]: Malformed ICMPv6 Packet", self.interface.name);
+      self.malformed("ICMPv6 Packet");
     }
   }
 
   fn handle_tcp_packet(&mut self, src_dest: SrcDest, packet: &[u8]) {
     let tcp = TcpPacket::new(packet);
     if let Some(tcp) = tcp {
+      let is_incoming = self.is_incoming(&src_dest);
+      let flow = Flow::directed(
+        Protocol::Tcp,
+        &src_dest,
+        tcp.get_source(),
+        tcp.get_destination(),
+        is_incoming,
+      );
+      self.attribution = Some(Attribution::Flow(flow, is_incoming));
+
       if let Some(handler) = self.handler_tcp_packet.as_mut() {
-        handler(&self.interface, &src_dest, &tcp);
+        handler(&self.interfaces[self.current], &src_dest, &tcp);
       }
     } else {
-      eprintln!("[{}]: Malformed TCP Packet", self.interface.name);
+      self.malformed("TCP Packet");
     }
   }
 
-  fn handle_udp_packet(&mut self, src_dest: SrcDest, packet: &[u8]) {
+  fn handle_udp_packet(&mut self, frame: Frame, src_dest: SrcDest, packet: &[u8]) {
     let udp = UdpPacket::new(packet);
 
     if let Some(udp) = udp {
+      let is_incoming = self.is_incoming(&src_dest);
+      let flow = Flow::directed(
+        Protocol::Udp,
+        &src_dest,
+        udp.get_source(),
+        udp.get_destination(),
+        is_incoming,
+      );
+      self.attribution = Some(Attribution::Flow(flow, is_incoming));
+
       if let Some(handler) = self.handler_udp_packet.as_mut() {
-        handler(&self.interface, &src_dest, &udp);
+        handler(&self.interfaces[self.current], &src_dest, &udp);
+      }
+
+      if self.decapsulate && self.tunnels.len() < MAX_TUNNEL_DEPTH {
+        let tunnel = match udp.get_destination() {
+          tunnel::VXLAN_PORT => tunnel::vxlan(udp.payload()),
+          tunnel::GENEVE_PORT => tunnel::geneve(udp.payload()),
+          _ => None,
+        };
+        if let Some((tunnel, ethertype, payload)) = tunnel {
+          self.handle_tunnel(frame, &src_dest, tunnel, ethertype, payload);
+        }
+      }
+    } else {
+      self.malformed("UDP Packet");
+    }
+  }
+
+  // Dispatches what a tunnel carries, which is either an Ethernet frame or an IP packet. Whatever's
+  // inside is attributed instead of the tunnel.
+  fn handle_tunnel(
+    &mut self,
+    frame: Frame,
+    src_dest: &SrcDest,
+    tunnel: Tunnel,
+    ethertype: EtherType,
+    payload: &[u8],
+  ) {
+    if let Some(handler) = self.handler_tunnel.as_mut() {
+      handler(&self.interfaces[self.current], src_dest, tunnel, payload);
+    }
+
+    self.tunnel_incoming = Some(self.is_incoming(src_dest));
+    self.tunnels.push(tunnel);
+    if ethertype != ETHERTYPE_TRANSPARENT_ETHERNET {
+      self.handle_ethertype(frame, ethertype, payload);
+    } else if let Some(inner) = EthernetPacket::new(payload) {
+      let frame = Frame {
+        captured: frame.captured,
+        inner: &inner,
+      };
+      self.handle_ethertype(frame, inner.get_ethertype(), inner.payload());
+    } else {
+      self.malformed(format!("{} Frame", tunnel));
+    }
+  }
+
+  // Counts something that couldn't be parsed, `what` is e.g. "TCP Packet".
+  fn malformed<D: Display>(&self, what: D) {
+    let mut stats = self.stats.lock().unwrap();
+    stats.malformed += 1;
+    stats.last_problem = Some(format!(
+      "[{}]: Malformed {}",
+      self.interfaces[self.current].name, what
+    ));
+  }
+
+  // Whether a packet from `src_dest` is incoming. Inside a tunnel that's whether the tunnel's
+  // packet was, since the addresses inside aren't the interface's.
+  fn is_incoming(&self, src_dest: &SrcDest) -> bool {
+    self
+      .tunnel_incoming
+      .unwrap_or_else(|| src_dest.1.is_incoming(&self.interfaces[self.current]))
+  }
+
+  // Our own echo requests and the replies to them are a flow, like ping sockets see them. Echoes
+  // the kernel answers (i.e., we're being pinged) and every other message go in the bucket.
+  // `is_request` is `None` for anything that isn't an echo.
+  fn echo_attribution(
+    &self,
+    protocol: Protocol,
+    src_dest: &SrcDest,
+    is_request: Option<bool>,
+    payload: &[u8],
+  ) -> Attribution {
+    let bucket = match protocol {
+      Protocol::Icmpv6 => Bucket::Icmpv6,
+      _ => Bucket::Icmp,
+    };
+
+    // Echoes start with a 16-bit identifier and sequence number.
+    let (is_request, identifier) = match is_request {
+      Some(is_request) if payload.len() >= 2 => {
+        (is_request, u16::from_be_bytes([payload[0], payload[1]]))
       }
+      _ => return Attribution::Bucket(bucket),
+    };
+
+    let is_incoming = self.is_incoming(src_dest);
+    let flow = Flow::echo(protocol, src_dest, identifier, is_incoming);
+    if is_request != is_incoming {
+      Attribution::Flow(flow, is_incoming)
     } else {
-      eprintln!("[{}]: Malformed UDP Packet", self.interface.name);
+      Attribution::Bucket(bucket)
     }
   }
 }
+
+// A frame (or a failure) forwarded from an interface's reader thread, tagged with the index of
+// the interface in `PacketMonitor::interfaces`.
+enum Captured {
+  Frame(usize, Duration, Vec<u8>),
+  Failed(usize, Error),
+}
+
+fn spawn_reader(
+  index: usize,
+  mut rx: Box<dyn DataLinkReceiver>,
+  tx: SyncSender<Captured>,
+  should_stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+  thread::spawn(move || {
+    while !should_stop.load(Ordering::SeqCst) {
+      let captured = match rx.next() {
+        Ok(packet) => Captured::Frame(index, now(), packet.to_vec()),
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+        Err(e) => {
+          let _ = tx.send(Captured::Failed(index, Error::capture(e)));
+          return;
+        }
+      };
+
+      // The other end only goes away once the capture is stopping.
+      if tx.send(captured).is_err() {
+        return;
+      }
+    }
+  })
+}
+
+fn no_interfaces() -> Error {
+  Error::Capture(io::Error::new(
+    io::ErrorKind::InvalidInput,
+    "no interfaces to capture on",
+  ))
+}
+
+fn now() -> Duration {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+}
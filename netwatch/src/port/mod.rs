@@ -1,8 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // TODO: make this a trait or generic across platforms
 use procfs::process::Process;
 
+use crate::connection::Protocol;
+
+pub mod socket;
+
+pub use socket::{Socket, TcpState};
+
+#[cfg(target_os = "linux")]
+#[path = "sock_diag_linux.rs"]
+pub mod sock_diag;
+
 #[cfg(target_os = "linux")]
 #[path = "port_linux.rs"]
 mod port_inner;
@@ -16,15 +29,207 @@ mod port_inner;
 mod port_inner;
 
 pub type Port = u16;
+pub type PID = i32;
+pub type Inode = u32;
+// The inode of a network namespace, as in `/proc/{PID}/ns/net -> net:[{INODE}]`.
+pub type Namespace = u64;
+
+// The IP protocol numbers raw sockets use as their port.
+const IPPROTO_ICMP: Port = 1;
+const IPPROTO_ICMPV6: Port = 58;
 
-#[derive(Debug)]
+// How long sockets and processes are remembered after they go away, so bytes that were sent just
+// before a process exited can still be attributed to it.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// Something about the sockets bound to each local address and port, in each network namespace.
+type Bindings<V> = HashMap<(Protocol, IpAddr, Port), HashMap<Option<Namespace>, V>>;
+
+// PortMapper is meant to be long-lived and refreshed periodically. It remembers which processes
+// own which sockets between refreshes, so each refresh only has to look at what has changed.
+#[derive(Debug, Clone)]
 pub struct PortMapper {
   // TODO: this shouldn't be public
-  pub inner: HashMap<Port, Vec<Process>>,
+  // Keyed by the address a socket is bound to, which is unspecified for wildcard-bound ones, and
+  // then by the network namespace it's in.
+  pub inner: Bindings<Vec<Process>>,
+  // Like `inner`, but the user that created the socket.
+  uids: Bindings<u32>,
+  // Each process that owns a socket, and the sockets it owns.
+  processes: HashMap<PID, Process>,
+  sockets: HashMap<PID, Vec<Socket>>,
+
+  // Every socket we know about, who owns it, and when it was last seen.
+  owned: HashMap<Inode, Owned>,
+  // Processes that own (or recently owned) a socket, and when each was last seen alive.
+  seen: HashMap<PID, Seen>,
+  // All PIDs as of the last refresh, so new processes can be found.
+  pids: HashSet<PID>,
+  // The network namespace of each of `pids`, and our own one.
+  namespaces: HashMap<PID, Namespace>,
+  // A socket for listing the sockets of our own namespace, kept between refreshes, or `None` if
+  // the kernel doesn't support it.
+  #[cfg(target_os = "linux")]
+  host_netlink: Option<Arc<sock_diag::Netlink>>,
+  // Sockets for listing the sockets of each of the other namespaces, or `None` for those we
+  // aren't allowed to join.
+  #[cfg(target_os = "linux")]
+  netlinks: HashMap<Namespace, Option<Arc<sock_diag::Netlink>>>,
+  host_namespace: Option<Namespace>,
+  last_full_scan: Option<Instant>,
+  grace_period: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Owned {
+  socket: Socket,
+  pids: Vec<PID>,
+  last_seen: Instant,
+}
+
+#[derive(Debug, Clone)]
+struct Seen {
+  process: Process,
+  // Read once, since it means reading the process's command line.
+  name: String,
+  last_seen: Instant,
+}
+
+impl Default for PortMapper {
+  fn default() -> PortMapper {
+    PortMapper::new()
+  }
 }
 
 impl PortMapper {
-  pub fn get(&self, port: &Port) -> Option<&Vec<Process>> {
-    self.inner.get(port)
+  /// How long sockets and processes that have gone away are kept around (5 seconds by default).
+  pub fn set_grace_period(&mut self, grace_period: Duration) {
+    self.grace_period = grace_period;
+  }
+
+  /// The processes with a socket bound to `addr` and `port`. Falls back to sockets bound to the
+  /// wildcard address (`0.0.0.0` or `::`), so that sockets bound to a specific address win.
+  ///
+  /// A packet doesn't say which network namespace it's for, so when sockets in several of them
+  /// are bound to the same address and port, ours wins. If it isn't one of them, there's no
+  /// telling whose the packet is, so there's no match rather than one mixing their processes.
+  pub fn get(&self, protocol: Protocol, addr: IpAddr, port: Port) -> Option<&Vec<Process>> {
+    find(&self.inner, protocol, addr, port).and_then(|bound| self.pick(bound))
+  }
+
+  /// The user that created the socket bound to `addr` and `port`, found like `get`. Only known
+  /// for sockets listed with `NETLINK_SOCK_DIAG`.
+  pub fn uid(&self, protocol: Protocol, addr: IpAddr, port: Port) -> Option<u32> {
+    find(&self.uids, protocol, addr, port)
+      .and_then(|bound| self.pick(bound))
+      .copied()
+  }
+
+  /// The process with `pid`, if it owned a socket when last refreshed.
+  pub fn process(&self, pid: PID) -> Option<&Process> {
+    self.processes.get(&pid)
+  }
+
+  /// The command line of `pid` (or its name if it doesn't have one, e.g., kernel threads), as of
+  /// when it first owned a socket.
+  pub fn name(&self, pid: PID) -> Option<&str> {
+    self.seen.get(&pid).map(|seen| &seen.name[..])
+  }
+
+  /// The network namespace `pid` was in when last refreshed.
+  pub fn namespace(&self, pid: PID) -> Option<Namespace> {
+    self.namespaces.get(&pid).copied()
+  }
+
+  /// Our own network namespace, which is the one traffic is attributed to first.
+  pub fn host_namespace(&self) -> Option<Namespace> {
+    self.host_namespace
+  }
+
+  /// The sockets owned by `pid` when last refreshed.
+  pub fn sockets(&self, pid: PID) -> &[Socket] {
+    self.sockets.get(&pid).map_or(&[], |sockets| &sockets[..])
+  }
+
+  // What's bound in our namespace, or in the only namespace with something bound.
+  fn pick<'a, V>(&self, bound: &'a HashMap<Option<Namespace>, V>) -> Option<&'a V> {
+    bound.get(&self.host_namespace).or_else(|| match bound.len() {
+      1 => bound.values().next(),
+      _ => None,
+    })
+  }
+}
+
+// Looks up the socket bound to `addr` and `port` in `map`, falling back to wildcard-bound ones.
+fn find<V>(
+  map: &HashMap<(Protocol, IpAddr, Port), V>,
+  protocol: Protocol,
+  addr: IpAddr,
+  port: Port,
+) -> Option<&V> {
+  let addr = socket::canonical(addr);
+  let wildcards: &[IpAddr] = match addr {
+    // Dual-stack sockets bound to `::` also accept IPv4.
+    IpAddr::V4(_) => &[
+      IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    ],
+    IpAddr::V6(_) => &[IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+  };
+
+  let value = std::iter::once(&addr)
+    .chain(wildcards)
+    .find_map(|addr| map.get(&(protocol, *addr, port)));
+
+  // Echoes that no ping socket was bound for may be for a raw socket (e.g., `traceroute` or a
+  // setuid `ping`), which gets every packet of its IP protocol.
+  value.or_else(|| match protocol {
+    Protocol::Icmp => find(map, Protocol::Raw, addr, IPPROTO_ICMP),
+    Protocol::Icmpv6 => find(map, Protocol::Raw, addr, IPPROTO_ICMPV6),
+    _ => None,
+  })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+  use super::*;
+
+  fn bind(port_mapper: &mut PortMapper, port: Port, namespace: Option<Namespace>) {
+    let key = (Protocol::Tcp, IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+    let process = Process::myself().unwrap();
+    port_mapper
+      .inner
+      .entry(key)
+      .or_default()
+      .insert(namespace, vec![process]);
+    port_mapper
+      .uids
+      .entry(key)
+      .or_default()
+      .insert(namespace, namespace.unwrap_or(0) as u32);
+  }
+
+  #[test]
+  fn namespaces_are_kept_apart() {
+    let mut port_mapper = PortMapper::new();
+    port_mapper.host_namespace = Some(1);
+    let addr = "10.0.0.1".parse().unwrap();
+
+    // Ours wins over a container's.
+    bind(&mut port_mapper, 80, Some(1));
+    bind(&mut port_mapper, 80, Some(2));
+    assert!(port_mapper.get(Protocol::Tcp, addr, 80).is_some());
+    assert_eq!(port_mapper.uid(Protocol::Tcp, addr, 80), Some(1));
+
+    // A single container's is all there is.
+    bind(&mut port_mapper, 443, Some(2));
+    assert!(port_mapper.get(Protocol::Tcp, addr, 443).is_some());
+    assert_eq!(port_mapper.uid(Protocol::Tcp, addr, 443), Some(2));
+
+    // Two containers' can't be told apart.
+    bind(&mut port_mapper, 8080, Some(2));
+    bind(&mut port_mapper, 8080, Some(3));
+    assert!(port_mapper.get(Protocol::Tcp, addr, 8080).is_none());
+    assert_eq!(port_mapper.uid(Protocol::Tcp, addr, 8080), None);
   }
 }
@@ -1,90 +1,466 @@
-use procfs::net::{tcp, tcp6, udp, udp6};
-use procfs::process::{all_processes, FDTarget, Process};
+use procfs::net::{self, read_tcp_table, read_udp_table};
+use procfs::process::{FDTarget, Process};
 
-use std::collections::{hash_map::Entry, HashMap};
-use std::fs::File;
-use std::io::{prelude::*, BufReader};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader};
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::connection::Protocol;
+use crate::error::{Error, Result};
+use crate::port::sock_diag::Netlink;
 use crate::port::*;
 
-pub type PID = i32;
-pub type Inode = u32;
 pub type InodePIDMap = HashMap<Inode, Vec<PID>>;
 
+// Processes that don't show up any other way are found by scanning every process, but at most
+// this often since that's expensive with a lot of processes.
+const FULL_SCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+const PROTOCOLS: [Protocol; 5] = [
+  Protocol::Tcp,
+  Protocol::Udp,
+  Protocol::Icmp,
+  Protocol::Icmpv6,
+  Protocol::Raw,
+];
+
 impl PortMapper {
   pub fn new() -> PortMapper {
     PortMapper {
       inner: HashMap::new(),
+      uids: HashMap::new(),
+      processes: HashMap::new(),
+      sockets: HashMap::new(),
+
+      owned: HashMap::new(),
+      seen: HashMap::new(),
+      pids: HashSet::new(),
+      namespaces: HashMap::new(),
+      host_netlink: Netlink::open().ok().map(Arc::new),
+      netlinks: HashMap::new(),
+      host_namespace: Self::get_namespace("/proc/self/ns/net"),
+      last_full_scan: None,
+      grace_period: DEFAULT_GRACE_PERIOD,
     }
   }
 
-  // TODO: how should we clean out old values?
-  pub fn refresh(&mut self) {
-    // Get map of `inode -> port`.
-    let inode_port_map = Self::get_inode_to_port();
-    // Get map of `inode -> [pid, pid, ...]`.
-    let inode_pid_map =
-      Self::get_inodes_to_pid_kernel_module().unwrap_or_else(|| Self::get_inodes_to_pid());
-
-    // Combine above information into a map of `port -> [process, process, ...]`.
-    for (inode, port) in inode_port_map {
-      if let Some(pids) = inode_pid_map.get(&inode) {
-        for pid in pids {
-          if let Some(process) = Process::new(*pid).ok() {
-            match self.inner.entry(port) {
-              Entry::Vacant(e) => {
-                e.insert(vec![process]);
-              }
-              Entry::Occupied(mut e) => {
-                e.get_mut().push(process);
-              }
+  /// Finds new sockets and their owners, and forgets those that have been gone for longer than
+  /// the grace period. Owners are found by scanning `/proc/{PID}/fd`, new processes first, then
+  /// those which already own sockets, then those running as the user that created a new socket,
+  /// and only if that isn't enough every process (at most every ten seconds). So the cost of a
+  /// refresh depends on how much has changed rather than on how many processes there are.
+  ///
+  /// Sockets in other network namespaces (e.g., containers) are listed through a netlink socket
+  /// opened in each namespace and kept between refreshes, or if we can't join a namespace, read
+  /// through one of the processes in it.
+  pub fn refresh(&mut self) -> Result<()> {
+    let now = Instant::now();
+
+    // A process's namespace rarely changes, so only look it up for new processes.
+    let pids = Self::get_pids()?;
+    self.namespaces.retain(|pid, _| pids.contains(pid));
+    for pid in pids.difference(&self.pids) {
+      if let Some(namespace) = Self::get_namespace(&format!("/proc/{}/ns/net", pid)) {
+        self.namespaces.insert(*pid, namespace);
+      }
+    }
+
+    // A PID can be reused between refreshes, so the processes that own sockets are read again to
+    // check they're still the same ones. Those that aren't lose their sockets, and are scanned
+    // again like new processes.
+    let mut reused = HashSet::new();
+    for (pid, seen) in self.seen.iter_mut() {
+      if !pids.contains(pid) {
+        continue;
+      }
+      // The process may have exited since we listed it.
+      if let Ok(process) = Process::new(*pid) {
+        if process.stat.starttime != seen.process.stat.starttime {
+          reused.insert(*pid);
+          seen.name = process_name(&process);
+        }
+        seen.process = process;
+        seen.last_seen = now;
+      }
+    }
+    for owned in self.owned.values_mut() {
+      owned.pids.retain(|pid| !reused.contains(pid));
+    }
+
+    let mut inode_socket_map = self.get_inode_to_socket()?;
+    let mut members: HashMap<Namespace, Vec<PID>> = HashMap::new();
+    for (pid, namespace) in self.namespaces.iter() {
+      if Some(*namespace) != self.host_namespace {
+        members
+          .entry(*namespace)
+          .or_default()
+          .push(*pid);
+      }
+    }
+    // Joined namespaces are kept alive by our sockets in them, so let go of those that are empty.
+    self
+      .netlinks
+      .retain(|namespace, _| members.contains_key(namespace));
+    for (namespace, pids) in members {
+      for socket in self.get_namespace_sockets(namespace, &pids) {
+        inode_socket_map.insert(socket.inode, socket);
+      }
+    }
+
+    // Sockets without an inode (e.g., in `TIME_WAIT`) don't belong to any process any more.
+    let mut unresolved = HashSet::new();
+    // The users that created sockets we hadn't seen before.
+    let mut creators = HashSet::new();
+    for (inode, socket) in inode_socket_map {
+      if inode == 0 {
+        continue;
+      }
+
+      match self.owned.entry(inode) {
+        Entry::Occupied(mut e) => {
+          let owned = e.get_mut();
+          owned.socket = socket;
+          owned.last_seen = now;
+          if owned.pids.is_empty() {
+            unresolved.insert(inode);
+          }
+        }
+        Entry::Vacant(e) => {
+          creators.extend(socket.uid);
+          e.insert(Owned {
+            socket,
+            pids: vec![],
+            last_seen: now,
+          });
+          unresolved.insert(inode);
+        }
+      }
+    }
+
+    if !unresolved.is_empty() {
+      match Self::get_inodes_to_pid_kernel_module()? {
+        // The kernel module lists everything at once, so there's no need to be clever.
+        Some(inode_pid_map) => {
+          for (inode, pids) in inode_pid_map {
+            for pid in pids {
+              self.add_owner(inode, pid, now, &mut unresolved);
             }
           }
         }
+        None => {
+          let new = pids
+            .difference(&self.pids)
+            .chain(reused.iter())
+            .copied()
+            .collect::<Vec<PID>>();
+          self.scan(&new, now, &mut unresolved);
+
+          if !unresolved.is_empty() {
+            let active = self
+              .seen
+              .keys()
+              .filter(|pid| pids.contains(pid) && !reused.contains(pid))
+              .copied()
+              .collect::<Vec<PID>>();
+            self.scan(&active, now, &mut unresolved);
+          }
+
+          // A process that has been around for a while can open its first socket, so look at the
+          // processes running as whoever created the new sockets rather than waiting for the next
+          // full scan. Only new sockets count, so those nobody can be found for (e.g., because
+          // we're not allowed to look) don't make every refresh do this.
+          if !unresolved.is_empty() && !creators.is_empty() {
+            let candidates = pids
+              .iter()
+              .filter(|pid| self.pids.contains(pid) && !reused.contains(pid))
+              .filter(|pid| !self.seen.contains_key(pid))
+              .filter(|pid| Self::get_uid(**pid).is_some_and(|uid| creators.contains(&uid)))
+              .copied()
+              .collect::<Vec<PID>>();
+            self.scan(&candidates, now, &mut unresolved);
+          }
+
+          let full_scan_due = self
+            .last_full_scan
+            .is_none_or(|last| now.duration_since(last) >= FULL_SCAN_INTERVAL);
+          if !unresolved.is_empty() && full_scan_due {
+            let all = pids.iter().copied().collect::<Vec<PID>>();
+            self.scan(&all, now, &mut unresolved);
+            self.last_full_scan = Some(now);
+          }
+        }
+      }
+    }
+
+    self.pids = pids;
+
+    // Forget whatever has been gone for longer than the grace period.
+    let grace_period = self.grace_period;
+    self
+      .owned
+      .retain(|_, owned| now.duration_since(owned.last_seen) <= grace_period);
+    self
+      .seen
+      .retain(|_, seen| now.duration_since(seen.last_seen) <= grace_period);
+    let seen = &self.seen;
+    for owned in self.owned.values_mut() {
+      owned.pids.retain(|pid| seen.contains_key(pid));
+    }
+
+    self.rebuild();
+
+    Ok(())
+  }
+
+  // Rebuilds the maps used for lookups from what we know about sockets and their owners.
+  fn rebuild(&mut self) {
+    self.inner.clear();
+    self.uids.clear();
+    self.processes.clear();
+    self.sockets.clear();
+
+    // Containers can bind the same addresses as us and each other (most often wildcard or
+    // loopback ones), so what's bound is kept apart by namespace, for `get` to choose from.
+    for owned in self.owned.values() {
+      let socket = owned.socket;
+      for pid in owned.pids.iter() {
+        let process = match self.seen.get(pid) {
+          Some(seen) => &seen.process,
+          None => continue,
+        };
+
+        self
+          .processes
+          .entry(*pid)
+          .or_insert_with(|| process.clone());
+        self
+          .sockets
+          .entry(*pid)
+          .or_default()
+          .push(socket);
+
+        let key = (
+          socket.protocol,
+          socket::canonical(socket.local.ip()),
+          socket.local.port(),
+        );
+        if let Some(uid) = socket.uid {
+          self
+            .uids
+            .entry(key)
+            .or_default()
+            .entry(socket.namespace)
+            .or_insert(uid);
+        }
+
+        match self.inner.entry(key).or_default().entry(socket.namespace) {
+          Entry::Vacant(e) => {
+            e.insert(vec![process.clone()]);
+          }
+          // A process can have several sockets on the same port (e.g., a listener and the
+          // connections it accepted), but should only be listed once.
+          Entry::Occupied(mut e) => {
+            if e.get().iter().all(|other| other.pid != process.pid) {
+              e.get_mut().push(process.clone());
+            }
+          }
+        }
+      }
+    }
+  }
+
+  // Records `pid` as an owner of the socket `inode`, if it's a socket we know about.
+  fn add_owner(&mut self, inode: Inode, pid: PID, now: Instant, unresolved: &mut HashSet<Inode>) {
+    let owned = match self.owned.get_mut(&inode) {
+      Some(owned) => owned,
+      None => return,
+    };
+
+    if let Entry::Vacant(e) = self.seen.entry(pid) {
+      // The process may have exited since we found its inodes, so just skip it.
+      match Process::new(pid) {
+        Ok(process) => {
+          e.insert(Seen {
+            name: process_name(&process),
+            process,
+            last_seen: now,
+          });
+        }
+        Err(_) => return,
+      }
+    }
+
+    if !owned.pids.contains(&pid) {
+      owned.pids.push(pid);
+    }
+    unresolved.remove(&inode);
+  }
+
+  // Reads the sockets of each of `pids` from `/proc/{PID}/fd/{FD}`.
+  fn scan(&mut self, pids: &[PID], now: Instant, unresolved: &mut HashSet<Inode>) {
+    for pid in pids {
+      let fds = match Process::new(*pid).and_then(|process| process.fd()) {
+        Ok(fds) => fds,
+        // The process may have exited, or we're not allowed to look at it.
+        Err(_) => continue,
+      };
+
+      for fd in fds {
+        if let FDTarget::Socket(inode) = fd.target {
+          self.add_owner(inode, *pid, now, unresolved);
+        }
       }
     }
   }
 
   // ---------------------
 
-  // Read from /proc/net/{tcp,udp}{,6}
-  fn get_inode_to_port() -> HashMap<Inode, Port> {
-    let mut inode_port_map = HashMap::new();
+  // Ask the kernel with `NETLINK_SOCK_DIAG`, falling back to /proc/net/{tcp,udp}{,6} for any
+  // protocol it can't answer for (e.g., if the `udp_diag` module isn't available). Ping and raw
+  // sockets always come from /proc/net.
+  fn get_inode_to_socket(&self) -> Result<HashMap<Inode, Socket>> {
+    let mut inode_socket_map = HashMap::new();
+
+    for protocol in PROTOCOLS.iter() {
+      let listed = self
+        .host_netlink
+        .as_ref()
+        .and_then(|netlink| netlink.sockets(*protocol).ok());
+      let sockets = match listed {
+        Some(sockets) => sockets,
+        None => Self::get_proc_sockets(Path::new("/proc/net"), *protocol)?,
+      };
 
-    let tcp = tcp().unwrap();
-    let tcp6 = tcp6().unwrap();
-    for entry in tcp.into_iter().chain(tcp6) {
-      // if entry.state == TcpState::Established || entry.state == TcpState::Listen {
-      inode_port_map.insert(entry.inode, entry.local_address.port());
-      // }
+      for mut socket in sockets {
+        socket.namespace = self.host_namespace;
+        inode_socket_map.insert(socket.inode, socket);
+      }
     }
 
-    let udp = udp().unwrap();
-    let udp6 = udp6().unwrap();
-    for entry in udp.into_iter().chain(udp6) {
-      // https://github.com/mattsta/netmatt/issues/1 ?
-      // if entry.state == UdpState::Established {
-      inode_port_map.insert(entry.inode, entry.local_address.port());
-      // }
+    Ok(inode_socket_map)
+  }
+
+  // Lists the sockets of another network namespace with `NETLINK_SOCK_DIAG`, through a socket
+  // opened in it the first time, like `get_inode_to_socket`. Anything that can't be listed that way
+  // is read from /proc/{PID}/net/* of the first of `pids` that's still around.
+  fn get_namespace_sockets(&mut self, namespace: Namespace, pids: &[PID]) -> Vec<Socket> {
+    let netlink = self.netlinks.entry(namespace).or_insert_with(|| {
+      pids
+        .iter()
+        .find_map(|pid| Netlink::open_in(Path::new(&format!("/proc/{}/ns/net", pid))).ok())
+        .map(Arc::new)
+    });
+
+    for pid in pids {
+      let dir = format!("/proc/{}/net", pid);
+      let mut sockets = vec![];
+      let read = PROTOCOLS.iter().try_for_each(|protocol| {
+        let listed = netlink
+          .as_ref()
+          .and_then(|netlink| netlink.sockets(*protocol).ok());
+        match listed {
+          Some(listed) => sockets.extend(listed),
+          None => sockets.extend(Self::get_proc_sockets(Path::new(&dir), *protocol)?),
+        }
+        Ok::<(), Error>(())
+      });
+
+      if read.is_ok() {
+        for socket in sockets.iter_mut() {
+          socket.namespace = Some(namespace);
+        }
+        return sockets;
+      }
     }
 
-    inode_port_map
+    vec![]
   }
 
-  // TODO: change this to a Result
-  fn get_inodes_to_pid_kernel_module() -> Option<InodePIDMap> {
+  // Read from {DIR}/{tcp,udp,icmp,raw}{,6}. Not every kernel has ping sockets, so their tables
+  // are allowed to be missing.
+  fn get_proc_sockets(dir: &Path, protocol: Protocol) -> Result<Vec<Socket>> {
+    let mut sockets = vec![];
+    let open = |name: &str| File::open(dir.join(name)).map(BufReader::new);
+
+    match protocol {
+      Protocol::Tcp => {
+        let tcp = read_tcp_table(open("tcp")?)?;
+        let tcp6 = read_tcp_table(open("tcp6")?)?;
+        for entry in tcp.into_iter().chain(tcp6) {
+          sockets.push(Socket {
+            protocol,
+            local: entry.local_address,
+            remote: entry.remote_address,
+            state: Some(tcp_state(&entry.state)),
+            uid: None,
+            inode: entry.inode,
+            namespace: None,
+          });
+        }
+      }
+      Protocol::Udp => {
+        let udp = read_udp_table(open("udp")?)?;
+        let udp6 = read_udp_table(open("udp6")?)?;
+        for entry in udp.into_iter().chain(udp6) {
+          // https://github.com/mattsta/netmatt/issues/1 ?
+          sockets.push(Socket {
+            protocol,
+            local: entry.local_address,
+            remote: entry.remote_address,
+            state: None,
+            uid: None,
+            inode: entry.inode,
+            namespace: None,
+          });
+        }
+      }
+      // These have the same format as the UDP tables.
+      Protocol::Icmp | Protocol::Icmpv6 | Protocol::Raw => {
+        let names: &[&str] = match protocol {
+          Protocol::Icmp => &["icmp"],
+          Protocol::Icmpv6 => &["icmp6"],
+          _ => &["raw", "raw6"],
+        };
+        for name in names {
+          let file = match open(name) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+          };
+          for entry in read_udp_table(file)? {
+            sockets.push(Socket {
+              protocol,
+              local: entry.local_address,
+              remote: entry.remote_address,
+              state: None,
+              uid: None,
+              inode: entry.inode,
+              namespace: None,
+            });
+          }
+        }
+      }
+    }
+
+    Ok(sockets)
+  }
+
+  fn get_inodes_to_pid_kernel_module() -> Result<Option<InodePIDMap>> {
     // Check if we have the kernel module installed.
     let kernel_module_path = Path::new("/proc/pid_inode_map");
     if kernel_module_path.exists() {
       let mut inode_pid_map = HashMap::new();
-      let file = File::open(&kernel_module_path).unwrap();
+      let file = File::open(kernel_module_path)?;
       let reader = BufReader::new(file);
 
       // Parses `/proc/pid_inode_map` which should in the format:
       //  PID 'PROCESS NAME' INODE INODE INODE...
       for line in reader.lines() {
-        let line = line.unwrap();
+        let line = line?;
         let parts = line
           .split("'")
           .map(|part| part.trim())
@@ -92,7 +468,12 @@ impl PortMapper {
 
         // NOTE: We don't need `parts[1]` (the name) yet, since we populate
         // that later by reading from `/proc/{PID}/cmdline`.
-        let (pid, inodes) = (parts[0].parse::<PID>().unwrap(), parts[2]);
+        // A line we can't make sense of only loses that process's sockets, so skip it rather than
+        // failing the whole refresh, like a process we can't read in `/proc`.
+        let (pid, inodes) = match (parts.first().map(|pid| pid.parse::<PID>()), parts.get(2)) {
+          (Some(Ok(pid)), Some(inodes)) => (pid, *inodes),
+          _ => continue,
+        };
 
         // TODO: de-dupe?
         let inodes = inodes
@@ -112,34 +493,70 @@ impl PortMapper {
         }
       }
 
-      Some(inode_pid_map)
+      Ok(Some(inode_pid_map))
     } else {
-      None
+      Ok(None)
     }
   }
 
-  // Fallback and read inodes and pids from `/proc/{PID}/fd/{FD}`.
-  // This is much slower as it needs to traverse each `PID` and read it.
-  fn get_inodes_to_pid() -> InodePIDMap {
-    let mut inode_pid_map = HashMap::new();
-
-    for process in all_processes().unwrap() {
-      if let Ok(fds) = process.fd() {
-        for fd in fds {
-          if let FDTarget::Socket(inode) = fd.target {
-            match inode_pid_map.entry(inode) {
-              Entry::Vacant(e) => {
-                e.insert(vec![process.pid]);
-              }
-              Entry::Occupied(mut e) => {
-                e.get_mut().push(process.pid);
-              }
-            }
-          }
-        }
+  // Lists every PID in `/proc`, which is much cheaper than reading each process.
+  fn get_pids() -> Result<HashSet<PID>> {
+    let mut pids = HashSet::new();
+    for entry in fs::read_dir("/proc")? {
+      if let Some(pid) = entry?
+        .file_name()
+        .to_str()
+        .and_then(|name| name.parse::<PID>().ok())
+      {
+        pids.insert(pid);
       }
     }
 
-    inode_pid_map
+    Ok(pids)
+  }
+
+  // The user a process runs as, from the owner of `/proc/{PID}`, which is much cheaper than reading
+  // the process.
+  fn get_uid(pid: PID) -> Option<u32> {
+    fs::metadata(format!("/proc/{}", pid))
+      .ok()
+      .map(|metadata| metadata.uid())
+  }
+
+  // Reads a namespace link like `/proc/{PID}/ns/net`, which points to `net:[{INODE}]`. Only works
+  // for processes we're allowed to inspect.
+  fn get_namespace(path: &str) -> Option<Namespace> {
+    let target = fs::read_link(path).ok()?;
+    target
+      .to_str()?
+      .trim_start_matches("net:[")
+      .trim_end_matches(']')
+      .parse()
+      .ok()
+  }
+}
+
+// The process's command line, or its name if it doesn't have one (e.g., kernel threads).
+fn process_name(process: &Process) -> String {
+  match process.cmdline() {
+    Ok(cmdline) if !cmdline.is_empty() => cmdline.join(" "),
+    _ => process.stat.comm.clone(),
+  }
+}
+
+fn tcp_state(state: &net::TcpState) -> TcpState {
+  match state {
+    net::TcpState::Established => TcpState::Established,
+    net::TcpState::SynSent => TcpState::SynSent,
+    net::TcpState::SynRecv => TcpState::SynRecv,
+    net::TcpState::FinWait1 => TcpState::FinWait1,
+    net::TcpState::FinWait2 => TcpState::FinWait2,
+    net::TcpState::TimeWait => TcpState::TimeWait,
+    net::TcpState::Close => TcpState::Close,
+    net::TcpState::CloseWait => TcpState::CloseWait,
+    net::TcpState::LastAck => TcpState::LastAck,
+    net::TcpState::Listen => TcpState::Listen,
+    net::TcpState::Closing => TcpState::Closing,
+    net::TcpState::NewSynRecv => TcpState::NewSynRecv,
   }
 }
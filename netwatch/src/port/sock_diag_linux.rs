@@ -0,0 +1,355 @@
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::thread;
+
+use crate::connection::Protocol;
+use crate::port::{Socket, TcpState};
+
+// See linux/sock_diag.h and linux/inet_diag.h.
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+
+// Sizes of `struct nlmsghdr`, `struct inet_diag_req_v2` and `struct inet_diag_msg`.
+const NLMSG_HEADER_LEN: usize = 16;
+const REQUEST_LEN: usize = 56;
+const MESSAGE_LEN: usize = 72;
+
+// TCP states in the order the kernel numbers them, starting from 1.
+const TCP_STATES: [TcpState; 12] = [
+  TcpState::Established,
+  TcpState::SynSent,
+  TcpState::SynRecv,
+  TcpState::FinWait1,
+  TcpState::FinWait2,
+  TcpState::TimeWait,
+  TcpState::Close,
+  TcpState::CloseWait,
+  TcpState::LastAck,
+  TcpState::Listen,
+  TcpState::Closing,
+  TcpState::NewSynRecv,
+];
+
+// Builds a `SOCK_DIAG_BY_FAMILY` request that dumps every socket.
+fn request(ip_protocol: u8, family: u8) -> Vec<u8> {
+  let len = NLMSG_HEADER_LEN + REQUEST_LEN;
+
+  let mut buffer = Vec::with_capacity(len);
+  buffer.extend_from_slice(&(len as u32).to_ne_bytes());
+  buffer.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+  buffer.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+  // Sequence number and port ID, neither of which we need.
+  buffer.extend_from_slice(&[0; 8]);
+
+  // Every state, and an empty `inet_diag_sockid`, i.e., don't match a specific socket.
+  buffer.extend_from_slice(&[family, ip_protocol, 0, 0]);
+  buffer.extend_from_slice(&(!0u32).to_ne_bytes());
+  buffer.extend_from_slice(&[0; 48]);
+
+  buffer
+}
+
+// Parses an `inet_diag_msg`.
+fn parse(protocol: Protocol, message: &[u8]) -> Option<Socket> {
+  if message.len() < MESSAGE_LEN {
+    return None;
+  }
+
+  let addr = |bytes: &[u8]| -> Option<IpAddr> {
+    match i32::from(message[0]) {
+      libc::AF_INET => Some(IpAddr::V4(Ipv4Addr::new(
+        bytes[0], bytes[1], bytes[2], bytes[3],
+      ))),
+      libc::AF_INET6 => {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(bytes);
+        Some(IpAddr::V6(Ipv6Addr::from(octets)))
+      }
+      _ => None,
+    }
+  };
+  let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+  let u32_at = |offset: usize| {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&message[offset..offset + 4]);
+    u32::from_ne_bytes(bytes)
+  };
+
+  Some(Socket {
+    protocol,
+    local: SocketAddr::new(addr(&message[8..24])?, port(&message[4..6])),
+    remote: SocketAddr::new(addr(&message[24..40])?, port(&message[6..8])),
+    state: match protocol {
+      Protocol::Tcp => message[1]
+        .checked_sub(1)
+        .and_then(|index| TCP_STATES.get(index as usize))
+        .copied(),
+      _ => None,
+    },
+    uid: Some(u32_at(64)),
+    inode: u32_at(68),
+    // The kernel only answers for the namespace of the netlink socket, which the caller knows.
+    namespace: None,
+  })
+}
+
+fn ip_protocol(protocol: Protocol) -> Option<u8> {
+  match protocol {
+    Protocol::Tcp => Some(libc::IPPROTO_TCP as u8),
+    Protocol::Udp => Some(libc::IPPROTO_UDP as u8),
+    _ => None,
+  }
+}
+
+/// A `NETLINK_SOCK_DIAG` socket, closed when dropped. It only answers for the network namespace
+/// it was opened in, and can be kept around to list that namespace's sockets again later.
+///
+/// Listing sockets this way is much faster than parsing `/proc/net/*`, but fails if the kernel
+/// doesn't support it (e.g., `udp_diag` isn't loaded), and for anything other than TCP and UDP.
+#[derive(Debug)]
+pub struct Netlink {
+  fd: RawFd,
+}
+
+impl Netlink {
+  /// Opens a socket in our own network namespace.
+  pub fn open() -> io::Result<Netlink> {
+    let fd = unsafe {
+      libc::socket(
+        libc::AF_NETLINK,
+        libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+        libc::NETLINK_SOCK_DIAG,
+      )
+    };
+    if fd == -1 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(Netlink { fd })
+  }
+
+  /// Opens a socket in the network namespace at `path` (e.g., `/proc/{PID}/ns/net`), which needs
+  /// `CAP_SYS_ADMIN`. Joining a namespace changes it for the whole thread, so that's done on a
+  /// thread of its own, and the socket stays in the namespace after the thread exits.
+  pub fn open_in(path: &Path) -> io::Result<Netlink> {
+    let namespace = File::open(path)?;
+    thread::spawn(move || {
+      if unsafe { libc::setns(namespace.as_raw_fd(), libc::CLONE_NEWNET) } == -1 {
+        return Err(io::Error::last_os_error());
+      }
+
+      Netlink::open()
+    })
+    .join()
+    .unwrap_or_else(|_| {
+      Err(io::Error::other("failed to join the network namespace"))
+    })
+  }
+
+  /// Lists the IPv4 and IPv6 sockets of `protocol` in this socket's namespace.
+  pub fn sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+    let ip_protocol = ip_protocol(protocol).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "{} sockets can't be listed with NETLINK_SOCK_DIAG",
+          protocol
+        ),
+      )
+    })?;
+    let mut sockets = vec![];
+    for family in [libc::AF_INET, libc::AF_INET6].iter() {
+      self.send(&request(ip_protocol, *family as u8))?;
+      self.receive(|message| {
+        if let Some(socket) = parse(protocol, message) {
+          sockets.push(socket);
+        }
+      })?;
+    }
+
+    Ok(sockets)
+  }
+
+  fn send(&self, request: &[u8]) -> io::Result<()> {
+    let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    kernel.nl_family = libc::AF_NETLINK as u16;
+
+    let result = unsafe {
+      libc::sendto(
+        self.fd,
+        request.as_ptr() as *const libc::c_void,
+        request.len(),
+        0,
+        &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+        mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+      )
+    };
+    if result == -1 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+  }
+
+  // Reads the reply to a dump request, calling `handle` with the payload of each message.
+  fn receive<F: FnMut(&[u8])>(&self, mut handle: F) -> io::Result<()> {
+    let mut buffer = vec![0u8; 65_536];
+    loop {
+      let len = unsafe {
+        libc::recv(
+          self.fd,
+          buffer.as_mut_ptr() as *mut libc::c_void,
+          buffer.len(),
+          0,
+        )
+      };
+      if len == -1 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::Interrupted {
+          continue;
+        }
+
+        return Err(e);
+      }
+
+      let mut messages = &buffer[..len as usize];
+      while messages.len() >= NLMSG_HEADER_LEN {
+        let mut header = [0; 4];
+        header.copy_from_slice(&messages[0..4]);
+        let message_len = u32::from_ne_bytes(header) as usize;
+        let message_type = i32::from(u16::from_ne_bytes([messages[4], messages[5]]));
+        if message_len < NLMSG_HEADER_LEN || message_len > messages.len() {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated netlink message",
+          ));
+        }
+
+        let payload = &messages[NLMSG_HEADER_LEN..message_len];
+        match message_type {
+          libc::NLMSG_DONE => return Ok(()),
+          libc::NLMSG_ERROR => {
+            let mut errno = [0; 4];
+            errno.copy_from_slice(payload.get(0..4).unwrap_or(&[0; 4]));
+            let errno = -i32::from_ne_bytes(errno);
+            // An error of zero is just an acknowledgement.
+            if errno != 0 {
+              return Err(io::Error::from_raw_os_error(errno));
+            }
+          }
+          _ => handle(payload),
+        }
+
+        // Messages are padded to a multiple of four bytes.
+        let aligned_len = (message_len + 3) & !3;
+        messages = &messages[aligned_len.min(messages.len())..];
+      }
+    }
+  }
+}
+
+impl Drop for Netlink {
+  fn drop(&mut self) {
+    unsafe {
+      libc::close(self.fd);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // An `inet_diag_msg` for a socket bound to `local` and connected to `remote`.
+  fn message(family: i32, state: u8, local: SocketAddr, remote: SocketAddr) -> Vec<u8> {
+    let octets = |addr: IpAddr| match addr {
+      IpAddr::V4(addr) => {
+        let mut octets = addr.octets().to_vec();
+        octets.resize(16, 0);
+        octets
+      }
+      IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+
+    let mut message = vec![family as u8, state, 0, 0];
+    message.extend_from_slice(&local.port().to_be_bytes());
+    message.extend_from_slice(&remote.port().to_be_bytes());
+    message.extend_from_slice(&octets(local.ip()));
+    message.extend_from_slice(&octets(remote.ip()));
+    // Interface, cookie, expiry and queue lengths.
+    message.extend_from_slice(&[0; 24]);
+    message.extend_from_slice(&1000u32.to_ne_bytes());
+    message.extend_from_slice(&12345u32.to_ne_bytes());
+    message
+  }
+
+  #[test]
+  fn parses_ipv4() {
+    let local = "10.0.0.1:40000".parse().unwrap();
+    let remote = "93.184.216.34:443".parse().unwrap();
+    let socket = parse(Protocol::Tcp, &message(libc::AF_INET, 1, local, remote)).unwrap();
+    assert_eq!(socket.protocol, Protocol::Tcp);
+    assert_eq!(socket.local, local);
+    assert_eq!(socket.remote, remote);
+    assert_eq!(socket.state, Some(TcpState::Established));
+    assert_eq!(socket.uid, Some(1000));
+    assert_eq!(socket.inode, 12345);
+  }
+
+  #[test]
+  fn parses_ipv6() {
+    let local = "[::]:5353".parse().unwrap();
+    let remote = "[::]:0".parse().unwrap();
+    let socket = parse(Protocol::Udp, &message(libc::AF_INET6, 7, local, remote)).unwrap();
+    assert_eq!(socket.local, local);
+    assert_eq!(socket.remote, remote);
+    // UDP sockets don't have a state, even though the kernel reports one.
+    assert_eq!(socket.state, None);
+  }
+
+  #[test]
+  fn tcp_states() {
+    let local = "10.0.0.1:80".parse().unwrap();
+    let remote = "0.0.0.0:0".parse().unwrap();
+    let state = |state| {
+      parse(Protocol::Tcp, &message(libc::AF_INET, state, local, remote))
+        .unwrap()
+        .state
+    };
+    assert_eq!(state(10), Some(TcpState::Listen));
+    assert_eq!(state(12), Some(TcpState::NewSynRecv));
+    assert_eq!(state(0), None);
+    assert_eq!(state(13), None);
+  }
+
+  #[test]
+  fn rejects_malformed_messages() {
+    let local = "10.0.0.1:80".parse().unwrap();
+    let remote = "0.0.0.0:0".parse().unwrap();
+    let message = message(libc::AF_INET, 1, local, remote);
+    assert!(parse(Protocol::Tcp, &message[..MESSAGE_LEN - 1]).is_none());
+
+    let mut unknown_family = message;
+    unknown_family[0] = libc::AF_UNIX as u8;
+    assert!(parse(Protocol::Tcp, &unknown_family).is_none());
+  }
+
+  #[test]
+  fn dump_request() {
+    let request = request(17, libc::AF_INET6 as u8);
+    assert_eq!(request.len(), NLMSG_HEADER_LEN + REQUEST_LEN);
+    assert_eq!(&request[0..4], &(request.len() as u32).to_ne_bytes());
+    assert_eq!(&request[4..6], &SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    assert_eq!(
+      &request[6..8],
+      &((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes()
+    );
+    assert_eq!(&request[16..20], &[libc::AF_INET6 as u8, 17, 0, 0]);
+    // Every state.
+    assert_eq!(&request[20..24], &[0xff; 4]);
+    assert!(request[24..].iter().all(|byte| *byte == 0));
+  }
+}
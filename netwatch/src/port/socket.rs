@@ -0,0 +1,107 @@
+use std::fmt::{Display, Error, Formatter};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::result::Result;
+
+use crate::connection::{Flow, Protocol};
+use crate::port::{Inode, Namespace};
+
+/// State of a TCP socket, named like `ss` and `netstat` show them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TcpState {
+  Established,
+  SynSent,
+  SynRecv,
+  FinWait1,
+  FinWait2,
+  TimeWait,
+  Close,
+  CloseWait,
+  LastAck,
+  Listen,
+  Closing,
+  NewSynRecv,
+}
+
+impl Display for TcpState {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    f.pad(match self {
+      TcpState::Established => "ESTABLISHED",
+      TcpState::SynSent => "SYN_SENT",
+      TcpState::SynRecv => "SYN_RECV",
+      TcpState::FinWait1 => "FIN_WAIT1",
+      TcpState::FinWait2 => "FIN_WAIT2",
+      TcpState::TimeWait => "TIME_WAIT",
+      TcpState::Close => "CLOSE",
+      TcpState::CloseWait => "CLOSE_WAIT",
+      TcpState::LastAck => "LAST_ACK",
+      TcpState::Listen => "LISTEN",
+      TcpState::Closing => "CLOSING",
+      TcpState::NewSynRecv => "NEW_SYN_RECV",
+    })
+  }
+}
+
+// A Socket is an entry from the kernel's socket tables, e.g. `/proc/net/tcp`.
+// Listening and unconnected sockets have an unspecified remote address (and port 0).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Socket {
+  pub protocol: Protocol,
+  pub local: SocketAddr,
+  pub remote: SocketAddr,
+  /// Only TCP sockets have a state.
+  pub state: Option<TcpState>,
+  /// The user that created the socket, when the source reports it.
+  pub uid: Option<u32>,
+  pub inode: Inode,
+  /// The network namespace the socket is in, when known.
+  pub namespace: Option<Namespace>,
+}
+
+impl Socket {
+  /// Whether the socket has a specific peer, rather than being a listening or unconnected one.
+  pub fn is_connected(&self) -> bool {
+    self.remote.port() != 0
+  }
+
+  /// Whether `flow` could be traffic on this socket. A listening or unconnected socket matches
+  /// every flow to its port, so prefer a connected socket when more than one matches.
+  pub fn matches(&self, flow: &Flow) -> bool {
+    if self.protocol != flow.protocol || self.local.port() != flow.local_port {
+      return false;
+    }
+
+    if !self.local.ip().is_unspecified() && canonical(self.local.ip()) != canonical(flow.local_addr)
+    {
+      return false;
+    }
+
+    !self.is_connected()
+      || (self.remote.port() == flow.remote_port
+        && canonical(self.remote.ip()) == canonical(flow.remote_addr))
+  }
+}
+
+impl Display for Socket {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+    write!(f, "{} {} <-> {}", self.protocol, self.local, self.remote)?;
+    if let Some(state) = self.state {
+      write!(f, " ({})", state)?;
+    }
+
+    Ok(())
+  }
+}
+
+// Dual-stack sockets show IPv4 peers as IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), but
+// packets carry plain IPv4 ones.
+pub(crate) fn canonical(addr: IpAddr) -> IpAddr {
+  match addr {
+    IpAddr::V6(v6) => match v6.octets() {
+      [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+      }
+      _ => addr,
+    },
+    _ => addr,
+  }
+}
@@ -0,0 +1,449 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::net::IpAddr;
+use std::time::Duration;
+
+// Limits on what's kept while waiting for the rest of a fragmented packet. Payloads can't be
+// longer than 64KiB (IPv6 jumbograms are never fragmented), which takes 54 fragments at IPv6's
+// minimum MTU, and packets that haven't been completed within 30 seconds are given up on, like
+// Linux does (see `ipfrag_time`). Everything held, fragments and what's held for each of them,
+// counts towards a budget of 4MiB across all packets (see `ipfrag_high_thresh`).
+const MAX_PAYLOAD_SIZE: usize = 65_535;
+const MAX_FRAGMENTS: usize = 64;
+const MAX_PACKETS: usize = 64;
+const MAX_HELD_BYTES: usize = 4 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Identifies the fragments of one packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+  pub source: IpAddr,
+  pub destination: IpAddr,
+  /// The upper-layer protocol.
+  pub protocol: u8,
+  /// The identification IPv4 has in its header, or IPv6 in its fragment header.
+  pub id: u32,
+}
+
+/// How many fragmented packets were put back together, and how many were given up on.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FragmentStats {
+  pub reassembled: u64,
+  /// Not every fragment arrived in time.
+  pub expired: u64,
+  /// The fragments were too big or didn't fit together, or room was needed for other packets.
+  pub dropped: u64,
+}
+
+/// What became of a fragment.
+#[derive(Debug)]
+pub enum Reassembly<T> {
+  /// More fragments are needed.
+  Incomplete,
+  /// The packet is complete, this is its whole payload and what was held for each fragment.
+  Complete(Vec<u8>, Vec<T>),
+  /// The packet was given up on, this is what was held for each fragment.
+  Dropped(Vec<T>),
+}
+
+/// Something held for a fragment.
+pub trait Held {
+  /// How many bytes it takes up, which counts towards how much the reassembler holds.
+  fn size(&self) -> usize;
+}
+
+struct Fragments<T> {
+  first_seen: Duration,
+  // Each fragment's data, by offset.
+  parts: BTreeMap<usize, Vec<u8>>,
+  size: usize,
+  // How many bytes the fragments and what's held for them take up.
+  held_bytes: usize,
+  // Where the payload ends, once the last fragment has arrived.
+  end: Option<usize>,
+  held: Vec<T>,
+}
+
+// Reassembler puts fragmented IP packets back together. Something is held for each fragment (e.g.,
+// the frame it came in) and handed back once its packet has been completed or given up on, so
+// fragments can be dealt with once it's known what they were part of.
+pub struct Reassembler<T> {
+  packets: HashMap<FragmentKey, Fragments<T>>,
+  // What was held for packets given up on since `abandoned` was last called.
+  abandoned: Vec<T>,
+  // How many bytes every packet's fragments and what's held for them take up.
+  held_bytes: usize,
+  stats: FragmentStats,
+}
+
+impl<T: Held> Default for Reassembler<T> {
+  fn default() -> Reassembler<T> {
+    Reassembler::new()
+  }
+}
+
+impl<T: Held> Reassembler<T> {
+  pub fn new() -> Reassembler<T> {
+    Reassembler {
+      packets: HashMap::new(),
+      abandoned: vec![],
+      held_bytes: 0,
+      stats: FragmentStats::default(),
+    }
+  }
+
+  /// Adds a fragment, whose data starts `offset` bytes into its packet's payload. `more` is
+  /// whether there are fragments after it, and `timestamp` is when it was captured.
+  pub fn insert(
+    &mut self,
+    key: FragmentKey,
+    timestamp: Duration,
+    offset: usize,
+    more: bool,
+    data: &[u8],
+    held: T,
+  ) -> Reassembly<T> {
+    // Make room by giving up on the oldest other packets.
+    let bytes = data.len() + held.size();
+    while (!self.packets.contains_key(&key) && self.packets.len() >= MAX_PACKETS)
+      || self.held_bytes + bytes > MAX_HELD_BYTES
+    {
+      let oldest = self
+        .packets
+        .iter()
+        .filter(|(other, _)| **other != key)
+        .min_by_key(|(_, fragments)| fragments.first_seen)
+        .map(|(key, _)| *key);
+      match oldest {
+        Some(oldest) => {
+          let fragments = self.remove(&oldest);
+          self.stats.dropped += 1;
+          self.abandoned.extend(fragments.held);
+        }
+        None => break,
+      }
+    }
+
+    let fragments = self.packets.entry(key).or_insert_with(|| Fragments {
+      first_seen: timestamp,
+      parts: BTreeMap::new(),
+      size: 0,
+      held_bytes: 0,
+      end: None,
+      held: vec![],
+    });
+    fragments.held.push(held);
+    fragments.size += data.len();
+    fragments.held_bytes += bytes;
+    self.held_bytes += bytes;
+
+    // Every fragment has to end before the last one does, and there can only be one last one.
+    let end = offset + data.len();
+    let fits = end <= MAX_PAYLOAD_SIZE
+      && fragments.size <= MAX_PAYLOAD_SIZE
+      && fragments.held.len() <= MAX_FRAGMENTS
+      && self.held_bytes <= MAX_HELD_BYTES
+      && match (fragments.end, more) {
+        (Some(last), _) => end <= last && (more || end == last),
+        (None, true) => true,
+        (None, false) => fragments
+          .parts
+          .iter()
+          .all(|(offset, data)| offset + data.len() <= end),
+      };
+    if !fits {
+      let fragments = self.remove(&key);
+      self.stats.dropped += 1;
+      return Reassembly::Dropped(fragments.held);
+    }
+
+    fragments.parts.insert(offset, data.to_vec());
+    if !more {
+      fragments.end = Some(end);
+    }
+
+    let end = match fragments.end {
+      Some(end) if is_complete(&fragments.parts, end) => end,
+      _ => return Reassembly::Incomplete,
+    };
+
+    let fragments = self.remove(&key);
+    let mut payload = vec![0u8; end];
+    for (offset, data) in fragments.parts.iter() {
+      payload[*offset..offset + data.len()].copy_from_slice(data);
+    }
+    self.stats.reassembled += 1;
+
+    Reassembly::Complete(payload, fragments.held)
+  }
+
+  /// Gives up on packets that have timed out by `now`, and returns what was held for those and
+  /// for any other packets given up on since this was last called.
+  pub fn abandoned(&mut self, now: Duration) -> Vec<T> {
+    let expired = self
+      .packets
+      .iter()
+      .filter(|(_, fragments)| {
+        now
+          .checked_sub(fragments.first_seen)
+          .is_some_and(|age| age > TIMEOUT)
+      })
+      .map(|(key, _)| *key)
+      .collect::<Vec<_>>();
+    for key in expired {
+      let fragments = self.remove(&key);
+      self.stats.expired += 1;
+      self.abandoned.extend(fragments.held);
+    }
+
+    mem::take(&mut self.abandoned)
+  }
+
+  pub fn stats(&self) -> FragmentStats {
+    self.stats
+  }
+
+  // Takes out a packet that's known to be held.
+  fn remove(&mut self, key: &FragmentKey) -> Fragments<T> {
+    let fragments = self.packets.remove(key).unwrap();
+    self.held_bytes -= fragments.held_bytes;
+    fragments
+  }
+}
+
+// Whether `parts` cover the whole payload, allowing for overlaps.
+fn is_complete(parts: &BTreeMap<usize, Vec<u8>>, end: usize) -> bool {
+  let mut covered = 0;
+  for (offset, data) in parts.iter() {
+    if *offset > covered {
+      return false;
+    }
+    covered = covered.max(offset + data.len());
+  }
+
+  covered >= end
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Stands in for a frame, `id` says which one.
+  #[derive(Debug, PartialEq, Eq)]
+  struct Frame {
+    id: u32,
+    size: usize,
+  }
+
+  impl Held for Frame {
+    fn size(&self) -> usize {
+      self.size
+    }
+  }
+
+  fn key(id: u32) -> FragmentKey {
+    FragmentKey {
+      source: "10.0.0.1".parse().unwrap(),
+      destination: "10.0.0.2".parse().unwrap(),
+      protocol: 17,
+      id,
+    }
+  }
+
+  fn frame(id: u32) -> Frame {
+    Frame { id, size: 100 }
+  }
+
+  fn ids(held: &[Frame]) -> Vec<u32> {
+    held.iter().map(|frame| frame.id).collect()
+  }
+
+  fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+  }
+
+  #[test]
+  fn in_order() {
+    let mut reassembler = Reassembler::new();
+    let first = reassembler.insert(key(1), secs(0), 0, true, &[1; 8], frame(1));
+    assert!(matches!(first, Reassembly::Incomplete));
+
+    match reassembler.insert(key(1), secs(0), 8, false, &[2; 4], frame(2)) {
+      Reassembly::Complete(payload, held) => {
+        assert_eq!(payload, [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(ids(&held), [1, 2]);
+      }
+      other => panic!("expected the packet to be complete, got {:?}", other),
+    }
+    assert_eq!(
+      reassembler.stats(),
+      FragmentStats {
+        reassembled: 1,
+        ..FragmentStats::default()
+      }
+    );
+  }
+
+  #[test]
+  fn out_of_order() {
+    let mut reassembler = Reassembler::new();
+    let last = reassembler.insert(key(1), secs(0), 16, false, &[3; 4], frame(3));
+    assert!(matches!(last, Reassembly::Incomplete));
+    let first = reassembler.insert(key(1), secs(0), 0, true, &[1; 8], frame(1));
+    assert!(matches!(first, Reassembly::Incomplete));
+
+    match reassembler.insert(key(1), secs(0), 8, true, &[2; 8], frame(2)) {
+      Reassembly::Complete(payload, held) => {
+        assert_eq!(&payload[..8], &[1; 8]);
+        assert_eq!(&payload[8..16], &[2; 8]);
+        assert_eq!(&payload[16..], &[3; 4]);
+        assert_eq!(ids(&held), [3, 1, 2]);
+      }
+      other => panic!("expected the packet to be complete, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn overlapping() {
+    let mut reassembler = Reassembler::new();
+    reassembler.insert(key(1), secs(0), 0, true, &[1; 16], frame(1));
+    match reassembler.insert(key(1), secs(0), 8, false, &[2; 16], frame(2)) {
+      // Later fragments overwrite what they overlap.
+      Reassembly::Complete(payload, _) => {
+        assert_eq!(payload.len(), 24);
+        assert_eq!(&payload[..8], &[1; 8]);
+        assert_eq!(&payload[8..], &[2; 16]);
+      }
+      other => panic!("expected the packet to be complete, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn packets_are_kept_apart() {
+    let mut reassembler = Reassembler::new();
+    reassembler.insert(key(1), secs(0), 0, true, &[1; 8], frame(1));
+    reassembler.insert(key(2), secs(0), 0, true, &[2; 8], frame(2));
+    match reassembler.insert(key(2), secs(0), 8, false, &[2; 8], frame(3)) {
+      Reassembly::Complete(payload, held) => {
+        assert_eq!(payload, [2; 16]);
+        assert_eq!(ids(&held), [2, 3]);
+      }
+      other => panic!("expected the packet to be complete, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn gaps_are_incomplete() {
+    let mut reassembler = Reassembler::new();
+    reassembler.insert(key(1), secs(0), 0, true, &[1; 8], frame(1));
+    let last = reassembler.insert(key(1), secs(0), 16, false, &[3; 8], frame(3));
+    assert!(matches!(last, Reassembly::Incomplete));
+  }
+
+  #[test]
+  fn inconsistent_fragments_are_dropped() {
+    // A fragment that ends after the last one.
+    let mut reassembler = Reassembler::new();
+    reassembler.insert(key(1), secs(0), 8, false, &[1; 8], frame(1));
+    match reassembler.insert(key(1), secs(0), 16, true, &[2; 8], frame(2)) {
+      Reassembly::Dropped(held) => assert_eq!(ids(&held), [1, 2]),
+      other => panic!("expected the packet to be dropped, got {:?}", other),
+    }
+
+    // Two last fragments that end in different places.
+    reassembler.insert(key(2), secs(0), 8, false, &[1; 8], frame(3));
+    let second = reassembler.insert(key(2), secs(0), 16, false, &[1; 8], frame(4));
+    assert!(matches!(second, Reassembly::Dropped(_)));
+
+    // A last fragment that ends before what's already arrived.
+    reassembler.insert(key(3), secs(0), 16, true, &[1; 8], frame(5));
+    let last = reassembler.insert(key(3), secs(0), 0, false, &[1; 8], frame(6));
+    assert!(matches!(last, Reassembly::Dropped(_)));
+
+    assert_eq!(reassembler.stats().dropped, 3);
+  }
+
+  #[test]
+  fn payloads_are_limited() {
+    let mut reassembler = Reassembler::new();
+    match reassembler.insert(key(1), secs(0), 65_528, true, &[0; 8], frame(1)) {
+      Reassembly::Dropped(held) => assert_eq!(ids(&held), [1]),
+      other => panic!("expected the packet to be dropped, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn fragments_per_packet_are_limited() {
+    let mut reassembler = Reassembler::new();
+    for i in 0..MAX_FRAGMENTS {
+      let fragment = reassembler.insert(key(1), secs(0), i * 8, true, &[0; 8], frame(i as u32));
+      assert!(matches!(fragment, Reassembly::Incomplete));
+    }
+
+    let offset = MAX_FRAGMENTS * 8;
+    match reassembler.insert(key(1), secs(0), offset, true, &[0; 8], frame(0)) {
+      Reassembly::Dropped(held) => assert_eq!(held.len(), MAX_FRAGMENTS + 1),
+      other => panic!("expected the packet to be dropped, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn packets_are_limited() {
+    let mut reassembler = Reassembler::new();
+    let millis = |id| Duration::from_millis(u64::from(id));
+    for id in 0..MAX_PACKETS as u32 {
+      reassembler.insert(key(id), millis(id), 0, true, &[0; 8], frame(id));
+    }
+    assert!(reassembler.abandoned(secs(1)).is_empty());
+
+    // The oldest packet makes way for a new one.
+    let id = MAX_PACKETS as u32;
+    reassembler.insert(key(id), millis(id), 0, true, &[0; 8], frame(id));
+    assert_eq!(ids(&reassembler.abandoned(secs(1))), [0]);
+    assert_eq!(reassembler.stats().dropped, 1);
+  }
+
+  #[test]
+  fn held_bytes_are_limited() {
+    let mut reassembler = Reassembler::new();
+    let big = |id| Frame {
+      id,
+      size: MAX_HELD_BYTES / 4,
+    };
+    for id in 0..3 {
+      reassembler.insert(key(id), secs(u64::from(id)), 0, true, &[0; 8], big(id));
+    }
+    assert!(reassembler.abandoned(secs(0)).is_empty());
+
+    // Room is made by giving up on the oldest packets.
+    reassembler.insert(key(3), secs(3), 0, true, &[0; 8], big(3));
+    assert_eq!(ids(&reassembler.abandoned(secs(3))), [0]);
+
+    // Packets that can't fit on their own are given up on.
+    let huge = Frame {
+      id: 4,
+      size: MAX_HELD_BYTES,
+    };
+    match reassembler.insert(key(4), secs(4), 0, true, &[0; 8], huge) {
+      Reassembly::Dropped(held) => assert_eq!(ids(&held), [4]),
+      other => panic!("expected the packet to be dropped, got {:?}", other),
+    }
+    assert_eq!(ids(&reassembler.abandoned(secs(4))), [1, 2, 3]);
+    assert_eq!(reassembler.held_bytes, 0);
+  }
+
+  #[test]
+  fn expiry() {
+    let mut reassembler = Reassembler::new();
+    reassembler.insert(key(1), secs(0), 0, true, &[0; 8], frame(1));
+    reassembler.insert(key(1), secs(20), 8, true, &[0; 8], frame(2));
+    reassembler.insert(key(2), secs(20), 0, true, &[0; 8], frame(3));
+
+    // Packets time out from when their first fragment arrived.
+    assert!(reassembler.abandoned(secs(30)).is_empty());
+    assert_eq!(ids(&reassembler.abandoned(secs(31))), [1, 2]);
+    assert_eq!(ids(&reassembler.abandoned(secs(51))), [3]);
+    assert_eq!(reassembler.stats().expired, 2);
+    assert_eq!(reassembler.held_bytes, 0);
+  }
+}
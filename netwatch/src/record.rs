@@ -0,0 +1,110 @@
+use pcap_file::pcapng::blocks::enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption};
+use pcap_file::pcapng::blocks::interface_description::{
+  InterfaceDescriptionBlock, InterfaceDescriptionOption,
+};
+use pcap_file::pcapng::PcapNgWriter;
+use pcap_file::DataLink;
+use pnet::datalink::NetworkInterface;
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{prelude::*, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::connection::Flow;
+use crate::error::Result;
+
+// Maximum number of bytes captured per frame (the same default as tcpdump).
+const SNAPLEN: u32 = 262_144;
+
+// Produces the comment for a frame of a flow.
+type Annotator = Box<dyn FnMut(&Flow) -> Option<String> + Send>;
+
+// Recorder writes frames to a pcapng file, optionally annotating each one with a comment.
+// Every interface that frames are recorded from gets its own interface description, written the
+// first time a frame from it is seen.
+// The comment is produced by the annotator from the frame's flow (if it has one), which lets
+// callers attach process information that Wireshark will show alongside the packet.
+pub struct Recorder {
+  writer: PcapNgWriter<BufWriter<File>>,
+  // Names of the interfaces described so far, the position of each is its pcapng interface id.
+  interfaces: Vec<String>,
+  annotator: Option<Annotator>,
+}
+
+impl Recorder {
+  pub fn create<P: AsRef<Path>>(path: P) -> Result<Recorder> {
+    let file = BufWriter::new(File::create(path)?);
+    let writer = PcapNgWriter::new(file)?;
+
+    Ok(Recorder {
+      writer,
+      interfaces: vec![],
+      annotator: None,
+    })
+  }
+
+  pub fn set_annotator<A: 'static + Send + FnMut(&Flow) -> Option<String>>(
+    &mut self,
+    annotator: A,
+  ) {
+    self.annotator = Some(Box::new(annotator));
+  }
+
+  /// Appends a single Ethernet frame captured on `interface`. `timestamp` is the time since the
+  /// UNIX epoch.
+  pub fn record(
+    &mut self,
+    interface: &NetworkInterface,
+    timestamp: Duration,
+    frame: &[u8],
+    flow: Option<&Flow>,
+  ) -> Result<()> {
+    let interface_id = self.interface_id(interface)?;
+
+    let comment = match (self.annotator.as_mut(), flow) {
+      (Some(annotator), Some(flow)) => annotator(flow),
+      _ => None,
+    };
+
+    let data = &frame[..frame.len().min(SNAPLEN as usize)];
+    let block = EnhancedPacketBlock {
+      interface_id,
+      timestamp,
+      original_len: frame.len() as u32,
+      data: Cow::Borrowed(data),
+      options: comment
+        .map(|comment| vec![EnhancedPacketOption::Comment(Cow::Owned(comment))])
+        .unwrap_or_default(),
+    };
+
+    self.writer.write_pcapng_block(block)?;
+
+    // Flush after every frame so the file is usable even if we're killed mid-capture.
+    self.writer.get_mut().flush()?;
+
+    Ok(())
+  }
+
+  fn interface_id(&mut self, interface: &NetworkInterface) -> Result<u32> {
+    if let Some(id) = self
+      .interfaces
+      .iter()
+      .position(|name| *name == interface.name)
+    {
+      return Ok(id as u32);
+    }
+
+    // Timestamps are written in nanoseconds, so the interface must declare that resolution.
+    let mut description = InterfaceDescriptionBlock::new(DataLink::ETHERNET, SNAPLEN);
+    description.options = vec![
+      InterfaceDescriptionOption::IfName(Cow::Owned(interface.name.clone())),
+      InterfaceDescriptionOption::IfTsResol(9),
+    ];
+    self.writer.write_pcapng_block(description)?;
+    self.interfaces.push(interface.name.clone());
+
+    Ok(self.interfaces.len() as u32 - 1)
+  }
+}
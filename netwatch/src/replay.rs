@@ -0,0 +1,171 @@
+use pcap_file::pcap::PcapReader;
+use pcap_file::pcapng::blocks::interface_description::{
+  InterfaceDescriptionBlock, InterfaceDescriptionOption,
+};
+use pcap_file::pcapng::{Block, PcapNgReader};
+use pcap_file::DataLink;
+
+use std::fs::File;
+use std::io::{prelude::*, BufReader, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::link::LinkType;
+
+// The first four bytes of every pcapng file (the Section Header Block type).
+const PCAPNG_MAGIC: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+
+/// How quickly a capture file should be fed through the handlers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pace {
+  /// As fast as the file can be read.
+  Fast,
+  /// Sleep between frames so they're delivered with their originally recorded spacing.
+  Original,
+}
+
+/// A single recorded link-layer frame.
+#[derive(Debug, Clone)]
+pub struct Frame {
+  /// Time since the UNIX epoch that the frame was captured.
+  pub timestamp: Duration,
+  /// Name of the interface the frame was captured on, if the file records it.
+  pub interface: Option<String>,
+  pub link_type: LinkType,
+  pub data: Vec<u8>,
+}
+
+enum Reader {
+  Pcap(PcapReader<BufReader<File>>),
+  PcapNg(PcapNgReader<BufReader<File>>),
+}
+
+// Replay reads frames back out of a pcap or pcapng file.
+// Only link types `PacketMonitor` can turn into Ethernet frames are supported.
+pub struct Replay {
+  reader: Reader,
+}
+
+impl Replay {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Replay> {
+    let mut file = File::open(path)?;
+
+    // Sniff the format from the magic number, then rewind so the reader sees the whole header.
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let file = BufReader::new(file);
+    let reader = if magic == PCAPNG_MAGIC {
+      Reader::PcapNg(PcapNgReader::new(file)?)
+    } else {
+      let reader = PcapReader::new(file)?;
+      link_type(reader.header().datalink)?;
+      Reader::Pcap(reader)
+    };
+
+    Ok(Replay { reader })
+  }
+
+  /// Returns the next frame in the file, or `None` once it has been exhausted.
+  pub fn next_frame(&mut self) -> Option<Result<Frame>> {
+    match &mut self.reader {
+      Reader::Pcap(reader) => {
+        let link_type = link_type(reader.header().datalink);
+        reader.next_packet().map(|packet| {
+          let packet = packet?;
+          Ok(Frame {
+            timestamp: packet.timestamp,
+            interface: None,
+            link_type: link_type?,
+            data: packet.data.into_owned(),
+          })
+        })
+      }
+      Reader::PcapNg(reader) => loop {
+        let block = match reader.next_block()? {
+          Ok(block) => block,
+          Err(e) => return Some(Err(e.into())),
+        };
+
+        // pcapng also contains metadata blocks, skip everything that isn't a packet.
+        let (interface_id, timestamp, data) = match block {
+          Block::EnhancedPacket(epb) => (epb.interface_id, epb.timestamp, epb.data.into_owned()),
+          Block::SimplePacket(spb) => (0, Duration::from_secs(0), spb.data.into_owned()),
+          _ => continue,
+        };
+
+        let interface = match reader.interfaces().get(interface_id as usize) {
+          Some(interface) => interface,
+          None => {
+            return Some(Err(Error::Parse(format!(
+              "packet references unknown interface: {}",
+              interface_id
+            ))))
+          }
+        };
+
+        let link_type = match link_type(interface.linktype) {
+          Ok(link_type) => link_type,
+          Err(e) => return Some(Err(e)),
+        };
+
+        return Some(Ok(Frame {
+          timestamp: interface_timestamp(interface, timestamp),
+          interface: interface_name(interface),
+          link_type,
+          data,
+        }));
+      },
+    }
+  }
+}
+
+fn link_type(data_link: DataLink) -> Result<LinkType> {
+  LinkType::from_data_link(data_link)
+    .ok_or_else(|| Error::Parse(format!("unsupported link type: {:?}", data_link)))
+}
+
+fn interface_name(interface: &InterfaceDescriptionBlock) -> Option<String> {
+  interface
+    .options
+    .iter()
+    .filter_map(|option| match option {
+      InterfaceDescriptionOption::IfName(name) => Some(name.to_string()),
+      _ => None,
+    })
+    .next()
+}
+
+// `pcap_file` hands back the raw pcapng timestamp as if it were in nanoseconds, but the real unit
+// is set per interface by `if_tsresol` (defaulting to microseconds), so scale it here.
+fn interface_timestamp(interface: &InterfaceDescriptionBlock, raw: Duration) -> Duration {
+  let resolution = interface
+    .options
+    .iter()
+    .filter_map(|option| match option {
+      InterfaceDescriptionOption::IfTsResol(resolution) => Some(*resolution),
+      _ => None,
+    })
+    .next()
+    .unwrap_or(6);
+
+  let units = raw.as_nanos();
+  let nanos = if resolution & 0x80 == 0 {
+    // Negative power of 10.
+    let exponent = u32::from(resolution);
+    if exponent <= 9 {
+      units * 10u128.pow(9 - exponent)
+    } else {
+      10u128.checked_pow(exponent - 9).map_or(0, |divisor| units / divisor)
+    }
+  } else {
+    // Negative power of 2.
+    (units * 1_000_000_000)
+      .checked_shr(u32::from(resolution & 0x7F))
+      .unwrap_or(0)
+  };
+
+  Duration::from_nanos(nanos as u64)
+}
@@ -2,13 +2,20 @@ use bytesize::ByteSize;
 
 use std::fmt::{Display, Formatter, Error};
 use std::result::Result;
+use std::time::{Duration, Instant};
 
-pub const DEFAULT_INTERVAL_MILLIS: u64 = 1_000;
-
+// Transfer counts the bytes sent and received since it was created or last reset.
 #[derive(Debug, Copy, Clone)]
 pub struct Transfer {
   incoming: u64,
   outgoing: u64,
+  since: Instant,
+}
+
+impl Default for Transfer {
+  fn default() -> Transfer {
+    Transfer::new()
+  }
 }
 
 impl Transfer {
@@ -16,6 +23,7 @@ impl Transfer {
     Transfer {
       incoming: 0,
       outgoing: 0,
+      since: Instant::now(),
     }
   }
 
@@ -27,15 +35,35 @@ impl Transfer {
     self.outgoing += incr;
   }
 
+  pub fn incoming(&self) -> u64 {
+    self.incoming
+  }
+
+  pub fn outgoing(&self) -> u64 {
+    self.outgoing
+  }
+
   pub fn reset(&mut self) {
     self.incoming = 0;
     self.outgoing = 0;
+    self.since = Instant::now();
+  }
+
+  /// Time since this was created or last reset.
+  pub fn elapsed(&self) -> Duration {
+    self.since.elapsed()
   }
 
-  pub fn stats(&self, millis: u64) -> (ByteSize, ByteSize) {
-    let normaliser = 1_000 / millis;
-    let incoming = ByteSize(self.incoming * normaliser);
-    let outgoing = ByteSize(self.outgoing * normaliser);
+  /// Incoming and outgoing bytes per second, if everything counted was transferred over
+  /// `elapsed`.
+  pub fn stats(&self, elapsed: Duration) -> (ByteSize, ByteSize) {
+    let seconds = elapsed.as_secs_f64();
+    if seconds == 0.0 {
+      return (ByteSize(0), ByteSize(0));
+    }
+
+    let incoming = ByteSize((self.incoming as f64 / seconds).round() as u64);
+    let outgoing = ByteSize((self.outgoing as f64 / seconds).round() as u64);
 
     (incoming, outgoing)
   }
@@ -43,12 +71,14 @@ impl Transfer {
   pub fn merge(&mut self, other: &Transfer) {
     self.incoming += other.incoming;
     self.outgoing += other.outgoing;
+    // The merged counts cover both periods.
+    self.since = self.since.min(other.since);
   }
 }
 
 impl Display for Transfer {
   fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-    let (incoming, outgoing) = self.stats(DEFAULT_INTERVAL_MILLIS);
+    let (incoming, outgoing) = self.stats(self.elapsed());
     let incoming = format!("{}", incoming);
     let outgoing = format!("{}", outgoing);
     f.pad(&format!("{:>8} {:>8}", incoming, outgoing))
@@ -0,0 +1,202 @@
+use pnet::packet::ethernet::EtherType;
+
+use std::fmt::{self, Display, Formatter};
+
+/// The UDP ports VXLAN and Geneve are sent to.
+pub const VXLAN_PORT: u16 = 4789;
+pub const GENEVE_PORT: u16 = 6081;
+
+/// What GRE and Geneve carry when it's a whole Ethernet frame rather than an IP packet (Transparent
+/// Ethernet Bridging).
+pub const ETHERTYPE_TRANSPARENT_ETHERNET: EtherType = EtherType(0x6558);
+
+// GRE flags and version. Routing is only in the old RFC 1701, and isn't supported.
+const GRE_CHECKSUM: u16 = 0x8000;
+const GRE_ROUTING: u16 = 0x4000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQUENCE: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+
+// The VXLAN flag that says the network identifier is valid, which is the only one defined.
+const VXLAN_VALID_VNI: u8 = 0x08;
+
+const TUNNEL_HEADER_LEN: usize = 8;
+
+/// A tunnel packets were decapsulated from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Tunnel {
+  /// IPv4 or IPv6 packets carried straight inside IPv4 or IPv6 (e.g., IPIP, SIT and 6in4).
+  IpInIp,
+  /// GRE, with its key if it has one.
+  Gre(Option<u32>),
+  /// VXLAN, with the network identifier.
+  Vxlan(u32),
+  /// Geneve, with the network identifier.
+  Geneve(u32),
+}
+
+impl Display for Tunnel {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Tunnel::IpInIp => f.pad("IP-in-IP"),
+      Tunnel::Gre(None) => f.pad("GRE"),
+      Tunnel::Gre(Some(key)) => f.pad(&format!("GRE key {}", key)),
+      Tunnel::Vxlan(vni) => f.pad(&format!("VXLAN {}", vni)),
+      Tunnel::Geneve(vni) => f.pad(&format!("Geneve {}", vni)),
+    }
+  }
+}
+
+/// Parses a GRE header, returning the tunnel, the ethertype of what it carries and that payload.
+/// Only version 0 is supported, version 1 (PPTP) carries PPP.
+pub fn gre(packet: &[u8]) -> Option<(Tunnel, EtherType, &[u8])> {
+  if packet.len() < 4 {
+    return None;
+  }
+
+  let flags = u16::from_be_bytes([packet[0], packet[1]]);
+  if flags & (GRE_ROUTING | GRE_VERSION) != 0 {
+    return None;
+  }
+
+  let protocol = EtherType(u16::from_be_bytes([packet[2], packet[3]]));
+  let mut offset = 4;
+  if flags & GRE_CHECKSUM != 0 {
+    offset += 4;
+  }
+  let key = if flags & GRE_KEY != 0 {
+    let key = packet.get(offset..offset + 4)?;
+    offset += 4;
+    Some(u32::from_be_bytes([key[0], key[1], key[2], key[3]]))
+  } else {
+    None
+  };
+  if flags & GRE_SEQUENCE != 0 {
+    offset += 4;
+  }
+
+  Some((Tunnel::Gre(key), protocol, packet.get(offset..)?))
+}
+
+/// Parses a VXLAN header, which is always followed by an Ethernet frame.
+pub fn vxlan(packet: &[u8]) -> Option<(Tunnel, EtherType, &[u8])> {
+  let header = packet.get(..TUNNEL_HEADER_LEN)?;
+  if header[0] & VXLAN_VALID_VNI == 0 {
+    return None;
+  }
+
+  Some((
+    Tunnel::Vxlan(network_identifier(header)),
+    ETHERTYPE_TRANSPARENT_ETHERNET,
+    &packet[TUNNEL_HEADER_LEN..],
+  ))
+}
+
+/// Parses a Geneve header, and skips its options.
+pub fn geneve(packet: &[u8]) -> Option<(Tunnel, EtherType, &[u8])> {
+  let header = packet.get(..TUNNEL_HEADER_LEN)?;
+  let version = header[0] >> 6;
+  if version != 0 {
+    return None;
+  }
+
+  // The options' length is in 4-byte words.
+  let options_len = (header[0] & 0x3f) as usize * 4;
+  let protocol = EtherType(u16::from_be_bytes([header[2], header[3]]));
+
+  Some((
+    Tunnel::Geneve(network_identifier(header)),
+    protocol,
+    packet.get(TUNNEL_HEADER_LEN + options_len..)?,
+  ))
+}
+
+// VXLAN and Geneve both have a 24-bit network identifier in the same place.
+fn network_identifier(header: &[u8]) -> u32 {
+  u32::from_be_bytes([0, header[4], header[5], header[6]])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pnet::packet::ethernet::EtherTypes;
+
+  #[test]
+  fn gre() {
+    let plain = [0x00, 0x00, 0x08, 0x00, 0x45];
+    assert_eq!(
+      super::gre(&plain),
+      Some((Tunnel::Gre(None), EtherTypes::Ipv4, &plain[4..]))
+    );
+
+    // Checksum, key and sequence number, each taking four bytes.
+    let mut optional = vec![0xb0, 0x00, 0x65, 0x58];
+    optional.extend_from_slice(&[0xff; 4]);
+    optional.extend_from_slice(&1234u32.to_be_bytes());
+    optional.extend_from_slice(&[0xee; 4]);
+    optional.extend_from_slice(&[0x02; 14]);
+    assert_eq!(
+      super::gre(&optional),
+      Some((
+        Tunnel::Gre(Some(1234)),
+        ETHERTYPE_TRANSPARENT_ETHERNET,
+        &optional[16..]
+      ))
+    );
+
+    // Routing, version 1 (PPTP), a truncated key and a truncated header.
+    assert_eq!(super::gre(&[0x40, 0x00, 0x08, 0x00]), None);
+    assert_eq!(super::gre(&[0x00, 0x01, 0x88, 0x0b]), None);
+    assert_eq!(super::gre(&[0x20, 0x00, 0x08, 0x00, 0, 0]), None);
+    assert_eq!(super::gre(&[0x00, 0x00, 0x08]), None);
+  }
+
+  #[test]
+  fn vxlan() {
+    let packet = [0x08, 0, 0, 0, 0x00, 0x10, 0x01, 0, 0x02];
+    assert_eq!(
+      super::vxlan(&packet),
+      Some((
+        Tunnel::Vxlan(0x1001),
+        ETHERTYPE_TRANSPARENT_ETHERNET,
+        &packet[8..]
+      ))
+    );
+
+    // The network identifier isn't valid, and a truncated header.
+    assert_eq!(super::vxlan(&[0x00, 0, 0, 0, 0, 0, 1, 0]), None);
+    assert_eq!(super::vxlan(&packet[..7]), None);
+  }
+
+  #[test]
+  fn geneve() {
+    let packet = [0x00, 0, 0x08, 0x00, 0, 0, 7, 0, 0x45];
+    assert_eq!(
+      super::geneve(&packet),
+      Some((Tunnel::Geneve(7), EtherTypes::Ipv4, &packet[8..]))
+    );
+
+    // Two words of options are skipped.
+    let mut options = vec![0x02, 0, 0x65, 0x58, 0, 0, 42, 0];
+    options.extend_from_slice(&[0xaa; 8]);
+    options.extend_from_slice(&[0x02; 14]);
+    assert_eq!(
+      super::geneve(&options),
+      Some((
+        Tunnel::Geneve(42),
+        ETHERTYPE_TRANSPARENT_ETHERNET,
+        &options[16..]
+      ))
+    );
+
+    // Options that run past the end, and an unknown version.
+    assert_eq!(super::geneve(&options[..12]), None);
+    assert_eq!(super::geneve(&[0x40, 0, 0x08, 0x00, 0, 0, 7, 0]), None);
+  }
+
+  #[test]
+  fn display() {
+    assert_eq!(Tunnel::Gre(Some(5)).to_string(), "GRE key 5");
+    assert_eq!(format!("{:<8}|", Tunnel::Gre(None)), "GRE     |");
+  }
+}
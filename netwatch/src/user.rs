@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::fs;
+
+use crate::error::Result;
+
+// Users maps user IDs to names. It's read once up front rather than for every lookup, since
+// names are needed for every row on every refresh.
+#[derive(Debug, Clone, Default)]
+pub struct Users {
+  names: HashMap<u32, String>,
+}
+
+impl Users {
+  /// Reads `/etc/passwd`. Users that only exist elsewhere (e.g., in LDAP) won't have a name.
+  pub fn read() -> Result<Users> {
+    Ok(Users::parse(&fs::read_to_string("/etc/passwd")?))
+  }
+
+  // Each line is `NAME:PASSWORD:UID:GID:GECOS:HOME:SHELL`. Like `getpwuid`, the first entry for
+  // a UID wins.
+  fn parse(passwd: &str) -> Users {
+    let mut names = HashMap::new();
+    for line in passwd.lines() {
+      let mut fields = line.split(':');
+      if let (Some(name), Some(Ok(uid))) = (fields.next(), fields.nth(1).map(str::parse::<u32>)) {
+        names.entry(uid).or_insert_with(|| name.to_string());
+      }
+    }
+
+    Users { names }
+  }
+
+  pub fn name(&self, uid: u32) -> Option<&str> {
+    self.names.get(&uid).map(String::as_str)
+  }
+
+  /// The user's name, or their UID if they don't have one.
+  pub fn display(&self, uid: u32) -> String {
+    self
+      .name(uid)
+      .map_or_else(|| uid.to_string(), str::to_string)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_passwd() {
+    let users = Users::parse(
+      "root:x:0:0:root:/root:/bin/bash\n\
+       # a comment\n\
+       \n\
+       alice:x:1000:1000:Alice,,,:/home/alice:/bin/zsh\n\
+       broken:x:not-a-uid:0::/:/bin/false\n\
+       toor:x:0:0::/root:/bin/sh\n",
+    );
+    assert_eq!(users.name(0), Some("root"));
+    assert_eq!(users.name(1000), Some("alice"));
+    assert_eq!(users.names.len(), 2);
+  }
+
+  #[test]
+  fn displays_uids_without_names() {
+    let users = Users::parse("alice:x:1000:1000::/home/alice:/bin/sh\n");
+    assert_eq!(users.display(1000), "alice");
+    assert_eq!(users.display(1001), "1001");
+  }
+}
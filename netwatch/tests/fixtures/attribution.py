@@ -0,0 +1,62 @@
+#!/usr/bin/env python3
+# Writes attribution.pcap, which tests/replay.rs replays. Run from this directory. Peers we send to
+# are off-link, since addresses on the interface's networks count as ours.
+import struct
+
+US, GATEWAY = bytes([2, 0, 0, 0, 0, 1]), bytes([2, 0, 0, 0, 0, 0xFE])
+LOCAL, DNS, WEB, PEER = [10, 0, 0, 1], [192, 0, 2, 53], [93, 184, 216, 34], [10, 0, 0, 7]
+LOCAL6 = bytes.fromhex("20010db8000000000000000000000001")
+REMOTE6 = bytes.fromhex("20010db8000100000000000000000035")
+
+
+def ethernet(destination, source, ethertype, payload):
+    return destination + source + struct.pack("!H", ethertype) + payload
+
+
+def ipv4(source, destination, protocol, payload, id=0, fragment=0):
+    header = struct.pack("!BBHHHBBH", 0x45, 0, 20 + len(payload), id, fragment, 64, protocol, 0)
+    return header + bytes(source) + bytes(destination) + payload
+
+
+def ipv6(source, destination, next_header, payload):
+    return struct.pack("!IHBB", 0x60000000, len(payload), next_header, 64) + source + destination + payload
+
+
+def tcp(source, destination, payload=b""):
+    return struct.pack("!HHIIBBHHH", source, destination, 0, 0, 0x50, 0x02, 65535, 0, 0) + payload
+
+
+def udp(source, destination, payload):
+    return struct.pack("!HHHH", source, destination, 8 + len(payload), 0) + payload
+
+
+dns_reply = udp(53, 5353, bytes(1200))
+frames = [
+    # ARP reply to us.
+    (0, ethernet(US, GATEWAY, 0x0806, bytes([0, 1, 8, 0, 6, 4, 0, 2]) + bytes(20))),
+    # A TCP connection's SYN, SYN-ACK and some data.
+    (1, ethernet(GATEWAY, US, 0x0800, ipv4(LOCAL, WEB, 6, tcp(40000, 443)))),
+    (1, ethernet(US, GATEWAY, 0x0800, ipv4(WEB, LOCAL, 6, tcp(443, 40000)))),
+    (2, ethernet(GATEWAY, US, 0x0800, ipv4(LOCAL, WEB, 6, tcp(40000, 443, bytes(100))))),
+    # A DNS query, and its reply in two fragments that arrive out of order.
+    (3, ethernet(GATEWAY, US, 0x0800, ipv4(LOCAL, DNS, 17, udp(5353, 53, bytes(30))))),
+    (4, ethernet(US, GATEWAY, 0x0800, ipv4(DNS, LOCAL, 17, dns_reply[1184:], id=7, fragment=1184 // 8))),
+    (4, ethernet(US, GATEWAY, 0x0800, ipv4(DNS, LOCAL, 17, dns_reply[:1184], id=7, fragment=0x2000))),
+    # UDP to us on VLAN 10.
+    (5, ethernet(US, GATEWAY, 0x8100, struct.pack("!HH", 10, 0x0800) + ipv4(PEER, LOCAL, 17, udp(7000, 6000, bytes(10))))),
+    # The first fragment of a packet whose other fragments never arrive.
+    (6, ethernet(US, GATEWAY, 0x0800, ipv4(PEER, LOCAL, 17, bytes(16), id=99, fragment=0x2000))),
+    # LLDP, which isn't IP.
+    (7, ethernet(US, GATEWAY, 0x88CC, bytes(20))),
+    # An IPv4 header that's cut short.
+    (8, ethernet(US, GATEWAY, 0x0800, bytes(10))),
+    # UDP over IPv6.
+    (9, ethernet(GATEWAY, US, 0x86DD, ipv6(LOCAL6, REMOTE6, 17, udp(5000, 53, bytes(12))))),
+    # Being pinged, late enough for the lone fragment to have expired.
+    (40, ethernet(US, GATEWAY, 0x0800, ipv4(WEB, LOCAL, 1, struct.pack("!BBHHH", 8, 0, 0, 1, 1) + bytes(4)))),
+]
+
+with open("attribution.pcap", "wb") as pcap:
+    pcap.write(struct.pack("<IHHiIII", 0xA1B2C3D4, 2, 4, 0, 0, 65535, 1))
+    for seconds, frame in frames:
+        pcap.write(struct.pack("<IIII", 1_600_000_000 + seconds, 0, len(frame), len(frame)) + frame)
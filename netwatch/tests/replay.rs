@@ -0,0 +1,268 @@
+use netwatch::connection::{Flow, Protocol};
+use netwatch::filter::Filter;
+use netwatch::incoming::IsIncoming;
+use netwatch::packet_monitor::{Attribution, Bucket, PacketMonitor};
+use netwatch::reassembly::FragmentStats;
+use netwatch::record::Recorder;
+use netwatch::replay::Pace;
+use pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketOption;
+use pcap_file::pcapng::{Block, PcapNgReader};
+use pnet::datalink::NetworkInterface;
+use pnet::util::MacAddr;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+// What's in the fixture is described in `fixtures/attribution.py`, which writes it.
+const FIXTURE: &str = concat!(
+  env!("CARGO_MANIFEST_DIR"),
+  "/tests/fixtures/attribution.pcap"
+);
+
+// Bytes in and out.
+type Totals = (u64, u64);
+
+fn interface() -> NetworkInterface {
+  NetworkInterface {
+    name: "eth0".into(),
+    description: String::new(),
+    index: 0,
+    mac: Some(MacAddr(2, 0, 0, 0, 0, 1)),
+    ips: vec![
+      "10.0.0.1/24".parse().unwrap(),
+      "2001:db8::1/64".parse().unwrap(),
+    ],
+    flags: 0,
+  }
+}
+
+fn flow(protocol: Protocol, local: &str, remote: &str) -> Attribution {
+  let (local, remote) = (
+    local.parse::<std::net::SocketAddr>().unwrap(),
+    remote.parse::<std::net::SocketAddr>().unwrap(),
+  );
+  Attribution::Flow(
+    Flow {
+      protocol,
+      local_addr: local.ip(),
+      local_port: local.port(),
+      remote_addr: remote.ip(),
+      remote_port: remote.port(),
+    },
+    // Direction is counted separately.
+    false,
+  )
+}
+
+fn add(totals: &mut Totals, is_incoming: bool, len: usize) {
+  if is_incoming {
+    totals.0 += len as u64;
+  } else {
+    totals.1 += len as u64;
+  }
+}
+
+#[test]
+fn attributes_every_frame() {
+  let mut monitor = PacketMonitor::new(interface());
+
+  let total = Arc::new(Mutex::new((0, 0)));
+  let total_ethernet = total.clone();
+  monitor.set_handler_ethernet_frame(move |iface, eth, len| {
+    add(
+      &mut total_ethernet.lock().unwrap(),
+      eth.is_incoming(iface),
+      len,
+    );
+  });
+
+  let attributed = Arc::new(Mutex::new(HashMap::<Attribution, Totals>::new()));
+  let vlans = Arc::new(Mutex::new(HashMap::<u16, Totals>::new()));
+  let (attributed_frames, vlan_frames) = (attributed.clone(), vlans.clone());
+  monitor.set_handler_attributed_frame(move |iface, eth, len, vlan, attribution| {
+    let (attribution, is_incoming) = match attribution {
+      Attribution::Flow(flow, is_incoming) => (Attribution::Flow(*flow, false), *is_incoming),
+      bucket => (*bucket, eth.is_incoming(iface)),
+    };
+    let mut attributed = attributed_frames.lock().unwrap();
+    add(attributed.entry(attribution).or_default(), is_incoming, len);
+    if let Some(vlan) = vlan {
+      add(
+        vlan_frames.lock().unwrap().entry(vlan).or_default(),
+        is_incoming,
+        len,
+      );
+    }
+  });
+
+  monitor.replay(FIXTURE, Pace::Fast).unwrap();
+
+  let expected = vec![
+    (Attribution::Bucket(Bucket::Arp), (42, 0)),
+    (
+      flow(Protocol::Tcp, "10.0.0.1:40000", "93.184.216.34:443"),
+      (54, 54 + 154),
+    ),
+    // The reply's fragments count towards it once they've been reassembled.
+    (
+      flow(Protocol::Udp, "10.0.0.1:5353", "192.0.2.53:53"),
+      (58 + 1218, 72),
+    ),
+    (
+      flow(Protocol::Udp, "10.0.0.1:6000", "10.0.0.7:7000"),
+      (56, 0),
+    ),
+    (Attribution::Bucket(Bucket::Fragments), (50, 0)),
+    (Attribution::Bucket(Bucket::Ethernet(0x88cc)), (34, 0)),
+    (Attribution::Bucket(Bucket::Malformed), (24, 0)),
+    (
+      flow(Protocol::Udp, "[2001:db8::1]:5000", "[2001:db8:1::35]:53"),
+      (0, 74),
+    ),
+    (Attribution::Bucket(Bucket::Icmp), (46, 0)),
+  ]
+  .into_iter()
+  .collect::<HashMap<_, _>>();
+  assert_eq!(*attributed.lock().unwrap(), expected);
+
+  // Flows and buckets add up to everything that was captured.
+  assert_eq!(*total.lock().unwrap(), (1582, 354));
+  assert_eq!(
+    *vlans.lock().unwrap(),
+    vec![(10, (56, 0))].into_iter().collect::<HashMap<_, _>>()
+  );
+
+  let stats = monitor.stats();
+  let stats = stats.lock().unwrap();
+  assert_eq!(
+    stats.fragments,
+    FragmentStats {
+      reassembled: 1,
+      expired: 1,
+      dropped: 0,
+    }
+  );
+  assert_eq!(stats.malformed, 1);
+}
+
+#[test]
+fn filters_tagged_frames() {
+  let mut monitor = PacketMonitor::new(interface());
+  monitor.set_filter(Filter::new("udp port 6000").unwrap());
+
+  let attributed = Arc::new(Mutex::new(vec![]));
+  let attributed_frames = attributed.clone();
+  monitor.set_handler_attributed_frame(move |_, _, len, vlan, attribution| {
+    let attribution = match attribution {
+      Attribution::Flow(flow, _) => Attribution::Flow(*flow, false),
+      bucket => *bucket,
+    };
+    attributed_frames
+      .lock()
+      .unwrap()
+      .push((attribution, len, vlan));
+  });
+
+  monitor.replay(FIXTURE, Pace::Fast).unwrap();
+
+  // Only the frame on VLAN 10 is for port 6000, which the filter sees past its tag. The DNS
+  // reply's second fragment has no ports to filter on so it's let through too, but without the
+  // first it's never reassembled.
+  assert_eq!(
+    *attributed.lock().unwrap(),
+    vec![
+      (
+        flow(Protocol::Udp, "10.0.0.1:6000", "10.0.0.7:7000"),
+        56,
+        Some(10)
+      ),
+      (Attribution::Bucket(Bucket::Fragments), 58, None),
+    ]
+  );
+}
+
+#[test]
+fn filters_fragmented_datagrams() {
+  let mut monitor = PacketMonitor::new(interface());
+  monitor.set_filter(Filter::new("udp port 5353").unwrap());
+
+  let attributed = Arc::new(Mutex::new(HashMap::<Attribution, usize>::new()));
+  let attributed_frames = attributed.clone();
+  monitor.set_handler_attributed_frame(move |_, _, len, _, attribution| {
+    let attribution = match attribution {
+      Attribution::Flow(flow, _) => Attribution::Flow(*flow, false),
+      bucket => *bucket,
+    };
+    *attributed_frames
+      .lock()
+      .unwrap()
+      .entry(attribution)
+      .or_default() += len;
+  });
+
+  monitor.replay(FIXTURE, Pace::Fast).unwrap();
+
+  // The reply's second fragment has no ports to filter on, but it's still let through to be
+  // reassembled with the first.
+  assert_eq!(
+    *attributed.lock().unwrap(),
+    vec![(
+      flow(Protocol::Udp, "10.0.0.1:5353", "192.0.2.53:53"),
+      72 + 58 + 1218
+    )]
+    .into_iter()
+    .collect::<HashMap<_, _>>()
+  );
+  assert_eq!(monitor.stats().lock().unwrap().fragments.reassembled, 1);
+}
+
+#[test]
+fn records_fragments_with_their_flow() {
+  let path = env::temp_dir().join(format!("netwatch-fragments-{}.pcapng", process::id()));
+  let mut recorder = Recorder::create(&path).unwrap();
+  recorder.set_annotator(|flow| Some(format!("{}:{}", flow.local_addr, flow.local_port)));
+
+  let mut monitor = PacketMonitor::new(interface());
+  monitor.set_recorder(recorder);
+  monitor.replay(FIXTURE, Pace::Fast).unwrap();
+  drop(monitor);
+
+  let mut reader = PcapNgReader::new(File::open(&path).unwrap()).unwrap();
+  let mut comments = vec![];
+  while let Some(block) = reader.next_block() {
+    if let Block::EnhancedPacket(epb) = block.unwrap() {
+      let comment = epb.options.iter().find_map(|option| match option {
+        EnhancedPacketOption::Comment(comment) => Some(comment.to_string()),
+        _ => None,
+      });
+      comments.push(comment);
+    }
+  }
+  fs::remove_file(&path).unwrap();
+
+  // Every frame is recorded, with held fragments written once it's known what they're part of.
+  let comment = |comment: &str| Some(comment.to_string());
+  assert_eq!(
+    comments,
+    vec![
+      None,
+      comment("10.0.0.1:40000"),
+      comment("10.0.0.1:40000"),
+      comment("10.0.0.1:40000"),
+      comment("10.0.0.1:5353"),
+      // Both of the reply's fragments, once the second one completes it.
+      comment("10.0.0.1:5353"),
+      comment("10.0.0.1:5353"),
+      comment("10.0.0.1:6000"),
+      None,
+      None,
+      comment("2001:db8::1:5000"),
+      // The lone fragment, given up on when the next frame arrives.
+      None,
+      None,
+    ]
+  );
+}
@@ -1,41 +1,303 @@
+use bytesize::ByteSize;
+use netwatch::connection::{
+  ConnectionList, ConnectionTable, Flow, GroupList, Grouping, Memberships,
+};
+use netwatch::history::Histories;
+use netwatch::packet_monitor::{Bucket, CaptureStats};
+use netwatch::port::{Namespace, PortMapper, Socket};
+use netwatch::reassembly::FragmentStats;
+use netwatch::transfer::Transfer;
+use netwatch::user::Users;
 use tui::backend;
-use tui::widgets::{Block, Borders, Widget};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Paragraph, Row, Table, Text, Widget};
 use tui::Terminal;
 
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Window used for the averages shown next to the current rates.
+const HISTORY_WINDOW: Duration = Duration::from_secs(10);
+
+// How many samples of history are kept per process and interface.
+const HISTORY_CAPACITY: usize = 60;
 
 pub enum AppEvent<I> {
   Input(I),
   Tick,
 }
 
+// Identifies a row in the process table, so it can stay selected when the rows are reordered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RowId {
+  Process(i32),
+  // A systemd unit, container or user, depending on the `Grouping`.
+  Group(String),
+  // Traffic we couldn't attribute to a process.
+  Unknown,
+  // Traffic that doesn't belong to any socket (e.g., ARP).
+  Bucket(Bucket),
+}
+
+impl RowId {
+  // Processes and groups come first, then unknown traffic, and then the buckets.
+  fn rank(&self) -> u8 {
+    match self {
+      RowId::Process(_) | RowId::Group(_) => 0,
+      RowId::Unknown => 1,
+      RowId::Bucket(_) => 2,
+    }
+  }
+}
+
+// The title of the process table for each grouping.
+fn grouping_title(grouping: Grouping) -> &'static str {
+  match grouping {
+    Grouping::Process => "Processes",
+    Grouping::Unit => "Units",
+    Grouping::Container => "Containers",
+    Grouping::User => "Users",
+  }
+}
+
+// Headers of the first two columns of the process table, which identify the row.
+fn grouping_headers(grouping: Grouping) -> (&'static str, &'static str) {
+  match grouping {
+    Grouping::Process => ("PID", "Command"),
+    Grouping::Unit => ("Procs", "Unit"),
+    Grouping::Container => ("Procs", "Container"),
+    Grouping::User => ("Procs", "User"),
+  }
+}
+
+/// The columns the process table can be sorted by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortColumn {
+  Download,
+  Upload,
+  Total,
+  Pid,
+  Name,
+}
+
+impl SortColumn {
+  const ALL: [SortColumn; 5] = [
+    SortColumn::Download,
+    SortColumn::Upload,
+    SortColumn::Total,
+    SortColumn::Pid,
+    SortColumn::Name,
+  ];
+
+  // Column in the table that shows what's being sorted by.
+  fn table_column(self) -> usize {
+    match self {
+      SortColumn::Pid => 0,
+      SortColumn::Name => 1,
+      SortColumn::Download => 2,
+      SortColumn::Upload => 3,
+      SortColumn::Total => 6,
+    }
+  }
+
+  // Numbers are biggest first by default, and names and PIDs are alphabetical.
+  fn descending(self) -> bool {
+    match self {
+      SortColumn::Download | SortColumn::Upload | SortColumn::Total => true,
+      SortColumn::Pid | SortColumn::Name => false,
+    }
+  }
+
+  fn compare(self, a: &ProcessRow, b: &ProcessRow) -> Ordering {
+    match self {
+      SortColumn::Download => a.rates.0.cmp(&b.rates.0),
+      SortColumn::Upload => a.rates.1.cmp(&b.rates.1),
+      SortColumn::Total => (a.total.0 + a.total.1).cmp(&(b.total.0 + b.total.1)),
+      SortColumn::Pid => a.processes.cmp(&b.processes),
+      SortColumn::Name => a.command.cmp(&b.command),
+    }
+  }
+}
+
+struct ProcessRow {
+  id: RowId,
+  command: String,
+  // How many processes are in the row's group (one for a process).
+  processes: usize,
+  rates: (ByteSize, ByteSize),
+  average: (ByteSize, ByteSize),
+  total: (ByteSize, ByteSize),
+}
+
+// What's shown in the detail pane for the selected process.
+struct ProcessDetail {
+  pid: i32,
+  ppid: i32,
+  // Only set when it isn't our own network namespace (e.g., it's in a container).
+  namespace: Option<Namespace>,
+  user: String,
+  cwd: String,
+  cmdline: String,
+  sockets: Vec<SocketRow>,
+}
+
+struct SocketRow {
+  socket: Socket,
+  rates: (ByteSize, ByteSize),
+}
+
+struct InterfaceRow {
+  name: String,
+  rates: (ByteSize, ByteSize),
+  average: (ByteSize, ByteSize),
+}
+
 pub struct App<'a> {
   pub title: &'a str,
   pub should_quit: bool,
+
+  // Shared with the capture handlers.
+  connections: Arc<Mutex<ConnectionTable>>,
+  interface_transfers: Arc<Mutex<HashMap<String, Transfer>>>,
+  vlan_transfers: Arc<Mutex<HashMap<(String, u16), Transfer>>>,
+  buckets: Arc<Mutex<HashMap<Bucket, Transfer>>>,
+  capture_stats: Arc<Mutex<CaptureStats>>,
+  // A copy of `port_mapper` as of the last tick, for the recorder. Only swapped in under the lock,
+  // so the recorder is never blocked by a refresh.
+  published: Arc<Mutex<Arc<PortMapper>>>,
+
+  // Refreshed on each tick, outside of any lock.
+  port_mapper: PortMapper,
+  last_tick: Instant,
+  interface_histories: Histories<String>,
+  vlan_histories: Histories<(String, u16)>,
+  process_histories: Histories<i32>,
+  // Everything transferred by each process since we started (only kept while it's in the history).
+  process_totals: HashMap<i32, Transfer>,
+  commands: HashMap<i32, String>,
+  memberships: Memberships,
+  unknown_total: Transfer,
+  // Like the above, but for the groups of the current grouping.
+  group_histories: Histories<String>,
+  group_totals: HashMap<String, Transfer>,
+  bucket_histories: Histories<Bucket>,
+  bucket_totals: HashMap<Bucket, Transfer>,
+
+  processes: Vec<ProcessRow>,
+  interfaces: Vec<InterfaceRow>,
+  // The VLANs seen recently on each interface, shown underneath it.
+  vlans: HashMap<String, Vec<InterfaceRow>>,
+  total: (ByteSize, ByteSize),
+  // The last error from refreshing processes, and problems with the captured frames as of the
+  // last tick, shown in the status bar.
+  error: Option<String>,
+  capture: CaptureStats,
+
+  grouping: Grouping,
+  sort_column: SortColumn,
+  sort_reversed: bool,
+  selected: Option<RowId>,
+  // Index of the first process row shown, so the selection can be scrolled to.
+  offset: usize,
+
+  // What each flow transferred during the last tick, and how long that tick was.
+  flows: Vec<(Flow, Transfer)>,
+  flows_elapsed: Duration,
+  detail: Option<ProcessDetail>,
 }
 
 impl<'a> App<'a> {
-  pub fn new(title: &'a str) -> App<'a> {
+  pub fn new(
+    title: &'a str,
+    connections: Arc<Mutex<ConnectionTable>>,
+    interface_transfers: Arc<Mutex<HashMap<String, Transfer>>>,
+    vlan_transfers: Arc<Mutex<HashMap<(String, u16), Transfer>>>,
+    buckets: Arc<Mutex<HashMap<Bucket, Transfer>>>,
+    capture_stats: Arc<Mutex<CaptureStats>>,
+    published: Arc<Mutex<Arc<PortMapper>>>,
+  ) -> App<'a> {
     App {
       title,
       should_quit: false,
+
+      connections,
+      interface_transfers,
+      vlan_transfers,
+      buckets,
+      capture_stats,
+      published,
+
+      port_mapper: PortMapper::new(),
+      last_tick: Instant::now(),
+      interface_histories: Histories::new(HISTORY_CAPACITY),
+      vlan_histories: Histories::new(HISTORY_CAPACITY),
+      process_histories: Histories::new(HISTORY_CAPACITY),
+      process_totals: HashMap::new(),
+      commands: HashMap::new(),
+      memberships: Memberships::new(Users::read().unwrap_or_default()),
+      unknown_total: Transfer::new(),
+      group_histories: Histories::new(HISTORY_CAPACITY),
+      group_totals: HashMap::new(),
+      bucket_histories: Histories::new(HISTORY_CAPACITY),
+      bucket_totals: HashMap::new(),
+
+      processes: vec![],
+      interfaces: vec![],
+      vlans: HashMap::new(),
+      total: (ByteSize(0), ByteSize(0)),
+      error: None,
+      capture: CaptureStats::default(),
+
+      grouping: Grouping::Process,
+      sort_column: SortColumn::Download,
+      sort_reversed: false,
+      selected: None,
+      offset: 0,
+
+      flows: vec![],
+      flows_elapsed: Duration::default(),
+      detail: None,
     }
   }
 
+  /// Selects the previous process.
   pub fn on_up(&mut self) {
-    // TODO:
+    let index = match self.selected_index() {
+      Some(index) => index.saturating_sub(1),
+      None => self.processes.len().saturating_sub(1),
+    };
+    self.select(index);
+    self.refresh_detail();
   }
 
+  /// Selects the next process.
   pub fn on_down(&mut self) {
-    // TODO:
+    let index = match self.selected_index() {
+      Some(index) => index + 1,
+      None => 0,
+    };
+    self.select(index);
+    self.refresh_detail();
+  }
+
+  /// Clears the selection, which closes the detail pane.
+  pub fn on_escape(&mut self) {
+    self.selected = None;
+    self.detail = None;
   }
 
+  /// Sorts by the next column.
   pub fn on_right(&mut self) {
-    // TODO:
+    self.cycle_sort_column(1);
   }
 
+  /// Sorts by the previous column.
   pub fn on_left(&mut self) {
-    // TODO:
+    self.cycle_sort_column(SortColumn::ALL.len() - 1);
   }
 
   pub fn on_key(&mut self, c: char) {
@@ -43,21 +305,637 @@ impl<'a> App<'a> {
       'q' => {
         self.should_quit = true;
       }
+      'r' => {
+        self.sort_reversed = !self.sort_reversed;
+        self.sort();
+      }
+      'g' => self.cycle_grouping(),
       _ => {}
     }
   }
 
+  fn cycle_sort_column(&mut self, step: usize) {
+    let current = SortColumn::ALL
+      .iter()
+      .position(|column| *column == self.sort_column)
+      .unwrap_or(0);
+    self.sort_column = SortColumn::ALL[(current + step) % SortColumn::ALL.len()];
+    self.sort();
+  }
+
+  fn cycle_grouping(&mut self) {
+    let current = Grouping::ALL
+      .iter()
+      .position(|grouping| *grouping == self.grouping)
+      .unwrap_or(0);
+    self.set_grouping(Grouping::ALL[(current + 1) % Grouping::ALL.len()]);
+  }
+
+  /// Switches what each row of the process table is. Group histories are only kept for the
+  /// current grouping, so they start over, and the rows are rebuilt on the next tick.
+  pub fn set_grouping(&mut self, grouping: Grouping) {
+    self.grouping = grouping;
+    self.group_histories = Histories::new(HISTORY_CAPACITY);
+    self.group_totals.clear();
+    self.processes.clear();
+    self.selected = None;
+    self.detail = None;
+  }
+
+  fn select(&mut self, index: usize) {
+    let index = index.min(self.processes.len().saturating_sub(1));
+    self.selected = self.processes.get(index).map(|process| process.id.clone());
+  }
+
+  fn selected_index(&self) -> Option<usize> {
+    let selected = self.selected.as_ref()?;
+    self
+      .processes
+      .iter()
+      .position(|process| process.id == *selected)
+  }
+
+  // Sorts the process table, keeping the unknown row and the buckets last.
+  fn sort(&mut self) {
+    let column = self.sort_column;
+    let descending = column.descending() != self.sort_reversed;
+    self.processes.sort_by(|a, b| {
+      if a.id.rank() != b.id.rank() {
+        return a.id.rank().cmp(&b.id.rank());
+      }
+
+      let ordering = match (&a.id, &b.id) {
+        (RowId::Unknown, RowId::Unknown) => return Ordering::Equal,
+        (RowId::Process(a_pid), RowId::Process(b_pid)) => {
+          column.compare(a, b).then(a_pid.cmp(b_pid))
+        }
+        _ => column.compare(a, b).then(a.command.cmp(&b.command)),
+      };
+
+      if descending {
+        ordering.reverse()
+      } else {
+        ordering
+      }
+    });
+  }
+
+  /// Collects everything transferred since the last tick and attributes it to processes.
   pub fn on_tick(&mut self) {
-    // TODO: update Transfers and iterate connections here
+    // Rates are over the time since the last tick, which isn't always the same.
+    let elapsed = self.last_tick.elapsed();
+    self.last_tick = Instant::now();
+
+    self.error = self
+      .port_mapper
+      .refresh()
+      .err()
+      .map(|e| format!("Failed to refresh processes: {}", e));
+    *self.published.lock().unwrap() = Arc::new(self.port_mapper.clone());
+    self.capture = self.capture_stats.lock().unwrap().clone();
+
+    // Interfaces...
+    {
+      let interface_transfers = &mut *self.interface_transfers.lock().unwrap();
+      self.interface_histories.tick(
+        interface_transfers
+          .iter()
+          .map(|(name, transfer)| (name.clone(), *transfer)),
+        elapsed,
+      );
+
+      let mut combined = Transfer::new();
+      self.interfaces = interface_transfers
+        .iter()
+        .map(|(name, transfer)| {
+          combined.merge(transfer);
+          InterfaceRow {
+            name: name.clone(),
+            rates: transfer.stats(elapsed),
+            average: self
+              .interface_histories
+              .get(name)
+              .map_or((ByteSize(0), ByteSize(0)), |history| {
+                history.average(HISTORY_WINDOW)
+              }),
+          }
+        })
+        .collect();
+      self.interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+      self.total = combined.stats(elapsed);
+
+      for transfer in interface_transfers.values_mut() {
+        transfer.reset();
+      }
+    }
+
+    // VLANs, which are already counted in their interface's totals...
+    {
+      let vlan_transfers = &mut *self.vlan_transfers.lock().unwrap();
+      self.vlan_histories.tick(
+        vlan_transfers
+          .iter()
+          .map(|(key, transfer)| (key.clone(), *transfer)),
+        elapsed,
+      );
+
+      let mut vlans = self
+        .vlan_histories
+        .iter()
+        .map(|(key, history)| {
+          let transfer = vlan_transfers
+            .get(key)
+            .copied()
+            .unwrap_or_else(Transfer::new);
+          (
+            key.clone(),
+            InterfaceRow {
+              name: format!("{}.{}", key.0, key.1),
+              rates: transfer.stats(elapsed),
+              average: history.average(HISTORY_WINDOW),
+            },
+          )
+        })
+        .collect::<Vec<_>>();
+      vlans.sort_by(|a, b| a.0.cmp(&b.0));
+      self.vlans.clear();
+      for ((interface, _), row) in vlans {
+        self
+          .vlans
+          .entry(interface)
+          .or_default()
+          .push(row);
+      }
+
+      for transfer in vlan_transfers.values_mut() {
+        transfer.reset();
+      }
+    }
+
+    // Buckets...
+    let mut buckets = HashMap::new();
+    for (bucket, transfer) in self.buckets.lock().unwrap().iter_mut() {
+      if transfer.incoming() > 0 || transfer.outgoing() > 0 {
+        buckets.insert(*bucket, *transfer);
+      }
+      transfer.reset();
+    }
+    for (bucket, transfer) in buckets.iter() {
+      self
+        .bucket_totals
+        .entry(*bucket)
+        .or_default()
+        .merge(transfer);
+    }
+    self.bucket_histories.tick(
+      buckets
+        .iter()
+        .map(|(bucket, transfer)| (*bucket, *transfer)),
+      elapsed,
+    );
+    let bucket_histories = &self.bucket_histories;
+    self
+      .bucket_totals
+      .retain(|bucket, _| bucket_histories.get(bucket).is_some());
+
+    // Processes, holding the connections lock for as short a time as possible...
+    let mut list = ConnectionList::new();
+    let mut groups = GroupList::new(self.grouping);
+    self.flows.clear();
+    self.flows_elapsed = elapsed;
+    {
+      let connections = &mut *self.connections.lock().unwrap();
+      for (flow, transfer) in connections {
+        self.flows.push((*flow, *transfer));
+        list.insert_flow(flow, transfer, &self.port_mapper);
+        if self.grouping != Grouping::Process {
+          groups.insert_flow(flow, transfer, &self.port_mapper, &mut self.memberships);
+        }
+
+        transfer.reset();
+      }
+    }
+
+    for (pid, (transfer, names)) in list.iter() {
+      self.process_totals.entry(pid).or_default().merge(transfer);
+      self.commands.entry(pid).or_insert_with(|| names.join(", "));
+    }
+    self.unknown_total.merge(list.unknown());
+
+    self.process_histories.tick(
+      list.iter().map(|(pid, (transfer, _))| (pid, *transfer)),
+      elapsed,
+    );
+
+    // Only show (and remember) processes with recent activity.
+    let process_histories = &self.process_histories;
+    self
+      .process_totals
+      .retain(|pid, _| process_histories.get(pid).is_some());
+    let process_totals = &self.process_totals;
+    self
+      .commands
+      .retain(|pid, _| process_totals.contains_key(pid));
+    self
+      .memberships
+      .retain(|pid| process_totals.contains_key(&pid));
+
+    for (group, transfer) in groups.iter() {
+      self
+        .group_totals
+        .entry(group.clone())
+        .or_default()
+        .merge(transfer);
+    }
+    self.group_histories.tick(
+      groups
+        .iter()
+        .map(|(group, transfer)| (group.clone(), *transfer)),
+      elapsed,
+    );
+    let group_histories = &self.group_histories;
+    self
+      .group_totals
+      .retain(|group, _| group_histories.get(group).is_some());
+
+    // Remember where the selection was, in case its process goes away.
+    let previous_index = self.selected_index();
+
+    let mut processes: Vec<ProcessRow> = match self.grouping {
+      Grouping::Process => self
+        .process_histories
+        .iter()
+        .map(|(pid, history)| ProcessRow {
+          id: RowId::Process(*pid),
+          command: self.commands.get(pid).cloned().unwrap_or_default(),
+          processes: 1,
+          rates: list
+            .get(*pid)
+            .map_or((ByteSize(0), ByteSize(0)), |(transfer, _)| {
+              transfer.stats(elapsed)
+            }),
+          average: history.average(HISTORY_WINDOW),
+          total: totals(&self.process_totals[pid]),
+        })
+        .collect(),
+      grouping => {
+        // How many of the processes with recent activity are in each group.
+        let mut counts = HashMap::new();
+        for membership in self.memberships.values() {
+          if let Some(group) = grouping.group(membership) {
+            *counts.entry(group).or_insert(0) += 1;
+          }
+        }
+
+        self
+          .group_histories
+          .iter()
+          .map(|(group, history)| ProcessRow {
+            id: RowId::Group(group.clone()),
+            command: group.clone(),
+            processes: counts.get(group).copied().unwrap_or(0),
+            rates: groups
+              .get(group)
+              .map_or((ByteSize(0), ByteSize(0)), |transfer| {
+                transfer.stats(elapsed)
+              }),
+            average: history.average(HISTORY_WINDOW),
+            total: totals(&self.group_totals[group]),
+          })
+          .collect()
+      }
+    };
+    processes.push(ProcessRow {
+      id: RowId::Unknown,
+      command: "<unknown>".into(),
+      processes: 0,
+      rates: list.unknown().stats(elapsed),
+      average: (ByteSize(0), ByteSize(0)),
+      total: totals(&self.unknown_total),
+    });
+    for (bucket, history) in self.bucket_histories.iter() {
+      processes.push(ProcessRow {
+        id: RowId::Bucket(*bucket),
+        command: format!("<{}>", bucket),
+        processes: 0,
+        rates: buckets
+          .get(bucket)
+          .map_or((ByteSize(0), ByteSize(0)), |transfer| {
+            transfer.stats(elapsed)
+          }),
+        average: history.average(HISTORY_WINDOW),
+        total: totals(&self.bucket_totals[bucket]),
+      });
+    }
+    self.processes = processes;
+    self.sort();
+
+    // Keep the same process selected, or select whatever took its place if it's gone.
+    if self.selected_index().is_none() {
+      if let Some(index) = previous_index {
+        self.select(index);
+      }
+    }
+
+    self.refresh_detail();
+  }
+
+  // Reads the selected process's details, and works out which of its sockets each flow was on.
+  fn refresh_detail(&mut self) {
+    let pid = match self.selected {
+      Some(RowId::Process(pid)) => pid,
+      _ => {
+        self.detail = None;
+        return;
+      }
+    };
+
+    let port_mapper = &self.port_mapper;
+    let process = match port_mapper.process(pid) {
+      Some(process) => process,
+      None => {
+        self.detail = None;
+        return;
+      }
+    };
+
+    let sockets = port_mapper.sockets(pid);
+    let mut transfers = vec![Transfer::new(); sockets.len()];
+    for (flow, transfer) in self.flows.iter() {
+      // A connected socket is a better match than a listening or unconnected one.
+      let best = sockets
+        .iter()
+        .position(|socket| socket.is_connected() && socket.matches(flow))
+        .or_else(|| sockets.iter().position(|socket| socket.matches(flow)));
+      if let Some(index) = best {
+        transfers[index].merge(transfer);
+      }
+    }
+
+    let mut sockets: Vec<SocketRow> = sockets
+      .iter()
+      .zip(transfers)
+      .map(|(socket, transfer)| SocketRow {
+        socket: *socket,
+        rates: transfer.stats(self.flows_elapsed),
+      })
+      .collect();
+    sockets.sort_by(|a, b| {
+      (b.rates.0 + b.rates.1)
+        .cmp(&(a.rates.0 + a.rates.1))
+        .then(a.socket.local.cmp(&b.socket.local))
+        .then(a.socket.remote.cmp(&b.socket.remote))
+    });
+
+    self.detail = Some(ProcessDetail {
+      pid,
+      ppid: process.stat.ppid,
+      namespace: port_mapper
+        .namespace(pid)
+        .filter(|namespace| Some(*namespace) != port_mapper.host_namespace()),
+      user: self.memberships.users().display(process.owner),
+      cwd: process
+        .cwd()
+        .map_or("?".into(), |cwd| cwd.display().to_string()),
+      cmdline: process
+        .cmdline()
+        .map_or_else(|_| process.stat.comm.clone(), |cmdline| cmdline.join(" ")),
+      sockets,
+    });
   }
 
   pub fn draw<B: backend::Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<(), io::Error> {
+    let header_style = Style::default().fg(Color::Yellow).modifier(Modifier::BOLD);
+    let row_style = Style::default().fg(Color::White);
+    let unknown_style = Style::default().fg(Color::DarkGray);
+    let selected_style = Style::default().fg(Color::Black).bg(Color::Cyan);
+
+    let mut summary = vec![Text::styled(
+      format!(
+        "Total   ↓ {:>10}/s ↑ {:>10}/s\n",
+        self.total.0, self.total.1
+      ),
+      Style::default().modifier(Modifier::BOLD),
+    )];
+    for interface in self.interfaces.iter() {
+      let vlans = self
+        .vlans
+        .get(&interface.name)
+        .map_or(&[][..], Vec::as_slice);
+      for row in std::iter::once(interface).chain(vlans) {
+        summary.push(Text::raw(format!(
+          "{:<8}↓ {:>10}/s ↑ {:>10}/s  (avg ↓ {}/s ↑ {}/s)\n",
+          row.name, row.rates.0, row.rates.1, row.average.0, row.average.1
+        )));
+      }
+    }
+    if let Some(error) = self.error.as_ref() {
+      summary.push(Text::styled(
+        format!("{}\n", error),
+        Style::default().fg(Color::Red),
+      ));
+    }
+    for failure in self.capture.failures.iter() {
+      summary.push(Text::styled(
+        format!("{}\n", failure),
+        Style::default().fg(Color::Red),
+      ));
+    }
+    let fragments = self.capture.fragments;
+    if fragments != FragmentStats::default() {
+      summary.push(Text::raw(format!(
+        "Fragmented packets: {} reassembled, {} expired, {} dropped\n",
+        fragments.reassembled, fragments.expired, fragments.dropped
+      )));
+    }
+    if let Some(problem) = self.capture.last_problem.as_ref() {
+      summary.push(Text::styled(
+        format!(
+          "{} malformed, {} not recorded (last: {})\n",
+          self.capture.malformed, self.capture.unrecorded, problem
+        ),
+        Style::default().fg(Color::Yellow),
+      ));
+    }
+
+    // When a process is selected the detail pane goes underneath, taking up at most half of the
+    // space (its info has four lines, and both it and the sockets table have borders, a header
+    // and a gap).
+    let size = terminal.size()?;
+    let summary_height = summary.len() as u16 + 2;
+    let constraints = match self.detail.as_ref() {
+      Some(detail) => {
+        let wanted = (4 + 2 + detail.sockets.len() + 4) as u16;
+        let available = size.height.saturating_sub(summary_height) / 2;
+        vec![
+          Constraint::Length(summary_height),
+          Constraint::Min(0),
+          Constraint::Length(wanted.min(available)),
+        ]
+      }
+      None => vec![Constraint::Length(summary_height), Constraint::Min(0)],
+    };
+    let chunks = Layout::default()
+      .direction(Direction::Vertical)
+      .constraints(constraints)
+      .split(size);
+
+    // Scroll so the selection is visible, the table's borders, header and the gap below the
+    // header take up four lines.
+    let visible = (chunks[1].height as usize).saturating_sub(4);
+    if let Some(index) = self.selected_index() {
+      if index < self.offset {
+        self.offset = index;
+      } else if visible > 0 && index >= self.offset + visible {
+        self.offset = index + 1 - visible;
+      }
+    }
+    self.offset = self
+      .offset
+      .min(self.processes.len().saturating_sub(visible));
+
+    let (id_header, name_header) = grouping_headers(self.grouping);
+    let mut header = vec![
+      id_header,
+      name_header,
+      "Down",
+      "Up",
+      "Avg Down",
+      "Avg Up",
+      "Total Down",
+      "Total Up",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect::<Vec<_>>();
+    let arrow = if self.sort_column.descending() != self.sort_reversed {
+      "▼"
+    } else {
+      "▲"
+    };
+    header[self.sort_column.table_column()].push_str(arrow);
+
+    let title = format!(
+      "{} (↑↓ select, ←→ sort, r reverse, g group)",
+      grouping_title(self.grouping)
+    );
+    let selected = self.selected.as_ref();
+    let rows = self.processes.iter().skip(self.offset).map(|process| {
+      let data = vec![
+        match &process.id {
+          RowId::Process(pid) => pid.to_string(),
+          RowId::Group(_) => process.processes.to_string(),
+          RowId::Unknown | RowId::Bucket(_) => "-".into(),
+        },
+        process.command.clone(),
+        format!("{}/s", process.rates.0),
+        format!("{}/s", process.rates.1),
+        format!("{}/s", process.average.0),
+        format!("{}/s", process.average.1),
+        process.total.0.to_string(),
+        process.total.1.to_string(),
+      ];
+      let style = if Some(&process.id) == selected {
+        selected_style
+      } else if process.id.rank() > 0 {
+        unknown_style
+      } else {
+        row_style
+      };
+      Row::StyledData(data.into_iter(), style)
+    });
+
+    let detail = self.detail.as_ref().map(|detail| {
+      let info = vec![
+        Text::raw(match detail.namespace {
+          Some(namespace) => format!(
+            "PID:     {} (parent {}, network namespace {})\n",
+            detail.pid, detail.ppid, namespace
+          ),
+          None => format!("PID:     {} (parent {})\n", detail.pid, detail.ppid),
+        }),
+        Text::raw(format!("User:    {}\n", detail.user)),
+        Text::raw(format!("Cwd:     {}\n", detail.cwd)),
+        Text::raw(format!("Command: {}\n", detail.cmdline)),
+      ];
+      let sockets = detail.sockets.iter().map(|row| {
+        let data = vec![
+          row.socket.protocol.to_string(),
+          row.socket.local.to_string(),
+          row.socket.remote.to_string(),
+          row
+            .socket
+            .state
+            .map_or(String::new(), |state| state.to_string()),
+          format!("{}/s", row.rates.0),
+          format!("{}/s", row.rates.1),
+        ];
+        Row::StyledData(data.into_iter(), row_style)
+      });
+
+      (info, sockets)
+    });
+
     terminal.draw(|mut f| {
-      let size = f.size();
-      Block::default()
-        .title(self.title)
-        .borders(Borders::ALL)
-        .render(&mut f, size);
+      Paragraph::new(summary.iter())
+        .block(Block::default().title(self.title).borders(Borders::ALL))
+        .render(&mut f, chunks[0]);
+
+      Table::new(header.iter(), rows)
+        .block(Block::default().title(&title).borders(Borders::ALL))
+        .header_style(header_style)
+        .widths(&[
+          Constraint::Length(7),
+          Constraint::Min(20),
+          Constraint::Length(12),
+          Constraint::Length(12),
+          Constraint::Length(12),
+          Constraint::Length(12),
+          Constraint::Length(12),
+          Constraint::Length(12),
+        ])
+        .render(&mut f, chunks[1]);
+
+      if let Some((info, sockets)) = detail {
+        let detail_chunks = Layout::default()
+          .direction(Direction::Vertical)
+          .constraints(
+            [
+              Constraint::Length(info.len() as u16 + 2),
+              Constraint::Min(0),
+            ]
+            .as_ref(),
+          )
+          .split(chunks[2]);
+
+        Paragraph::new(info.iter())
+          .block(
+            Block::default()
+              .title("Process (esc to close)")
+              .borders(Borders::ALL),
+          )
+          .render(&mut f, detail_chunks[0]);
+
+        Table::new(
+          ["Proto", "Local", "Remote", "State", "Down", "Up"].iter(),
+          sockets,
+        )
+        .block(Block::default().title("Sockets").borders(Borders::ALL))
+        .header_style(header_style)
+        .widths(&[
+          Constraint::Length(5),
+          Constraint::Min(22),
+          Constraint::Min(22),
+          Constraint::Length(12),
+          Constraint::Length(12),
+          Constraint::Length(12),
+        ])
+        .render(&mut f, detail_chunks[1]);
+      }
     })
   }
 }
+
+fn totals(transfer: &Transfer) -> (ByteSize, ByteSize) {
+  (ByteSize(transfer.incoming()), ByteSize(transfer.outgoing()))
+}
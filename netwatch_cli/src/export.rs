@@ -0,0 +1,183 @@
+use netwatch::connection::{ConnectionList, ConnectionTable, GroupList, Grouping, Memberships};
+use netwatch::packet_monitor::CaptureHandle;
+use netwatch::port::PortMapper;
+use netwatch::transfer::Transfer;
+use netwatch::user::Users;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The machine-readable formats traffic can be written in instead of showing the UI.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+  /// A header, then a row per process (or group) each interval.
+  Csv,
+  /// An object per process (or group) each interval, each on a line of its own.
+  Json,
+}
+
+impl Format {
+  pub fn from_name(name: &str) -> Option<Format> {
+    match name {
+      "csv" => Some(Format::Csv),
+      "json" => Some(Format::Json),
+      _ => None,
+    }
+  }
+}
+
+/// The name of a grouping on the command line and in exports.
+pub fn grouping_name(grouping: Grouping) -> &'static str {
+  match grouping {
+    Grouping::Process => "process",
+    Grouping::Unit => "unit",
+    Grouping::Container => "container",
+    Grouping::User => "user",
+  }
+}
+
+// What a process or group transferred during an interval. Groups are named by themselves, and
+// processes by their PID and command line.
+struct Row {
+  group: String,
+  name: String,
+  transfer: Transfer,
+}
+
+/// Writes what each process (or group of them) transferred to `out` every `interval`, until the
+/// capture stops. Like the UI, this refreshes its own port mapper and publishes a copy of it to
+/// `published` after each refresh.
+pub fn run<W: Write>(
+  mut out: W,
+  format: Format,
+  grouping: Grouping,
+  interval: Duration,
+  connections: &Mutex<ConnectionTable>,
+  published: &Mutex<Arc<PortMapper>>,
+  capture: &CaptureHandle,
+) -> io::Result<()> {
+  let mut port_mapper = PortMapper::new();
+  let mut memberships = Memberships::new(Users::read().unwrap_or_default());
+
+  if format == Format::Csv {
+    writeln!(out, "timestamp,grouping,group,name,incoming,outgoing")?;
+  }
+
+  while !capture.is_finished() {
+    thread::sleep(interval);
+
+    // Failing to refresh only means some traffic can't be attributed, so carry on.
+    let _ = port_mapper.refresh();
+    *published.lock().unwrap() = Arc::new(port_mapper.clone());
+
+    let mut list = ConnectionList::new();
+    let mut groups = GroupList::new(grouping);
+    {
+      let connections = &mut *connections.lock().unwrap();
+      for (flow, transfer) in connections {
+        match grouping {
+          Grouping::Process => {
+            list.insert_flow(flow, transfer, &port_mapper);
+          }
+          _ => groups.insert_flow(flow, transfer, &port_mapper, &mut memberships),
+        }
+        transfer.reset();
+      }
+    }
+    memberships.retain(|pid| port_mapper.process(pid).is_some());
+
+    let mut rows: Vec<Row> = match grouping {
+      Grouping::Process => list
+        .iter()
+        .map(|(pid, (transfer, names))| Row {
+          group: pid.to_string(),
+          name: names.join(", "),
+          transfer: *transfer,
+        })
+        .collect(),
+      _ => groups
+        .iter()
+        .map(|(group, transfer)| Row {
+          group: group.clone(),
+          name: group.clone(),
+          transfer: *transfer,
+        })
+        .collect(),
+    };
+    let unknown = match grouping {
+      Grouping::Process => list.unknown(),
+      _ => groups.unknown(),
+    };
+    rows.push(Row {
+      group: "<unknown>".into(),
+      name: "<unknown>".into(),
+      transfer: *unknown,
+    });
+
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |since| since.as_secs());
+    for row in rows {
+      let (incoming, outgoing) = (row.transfer.incoming(), row.transfer.outgoing());
+      if incoming + outgoing == 0 {
+        continue;
+      }
+
+      match format {
+        Format::Csv => writeln!(
+          out,
+          "{},{},{},{},{},{}",
+          timestamp,
+          grouping_name(grouping),
+          csv(&row.group),
+          csv(&row.name),
+          incoming,
+          outgoing
+        )?,
+        Format::Json => writeln!(
+          out,
+          "{{\"timestamp\":{},\"grouping\":\"{}\",\"group\":{},\"name\":{},\"incoming\":{},\"outgoing\":{}}}",
+          timestamp,
+          grouping_name(grouping),
+          json(&row.group),
+          json(&row.name),
+          incoming,
+          outgoing
+        )?,
+      }
+    }
+    out.flush()?;
+  }
+
+  Ok(())
+}
+
+// Quotes a CSV field if it needs to be.
+fn csv(field: &str) -> String {
+  if field.contains(&[',', '"', '\n', '\r'][..]) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+// A JSON string.
+fn json(value: &str) -> String {
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
@@ -1,48 +1,106 @@
 use crossterm::event::{self, Event, KeyCode};
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::ExecutableCommand;
 use pnet::datalink::{self, NetworkInterface};
-use pnet::packet::Packet;
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
 
+use std::collections::HashMap;
 use std::env;
-use std::io::{self, stdout, Write};
+use std::io::stdout;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use netwatch::connection::ConnectionTable;
+use netwatch::connection::{ConnectionTable, Grouping};
+use netwatch::filter::Filter;
 use netwatch::incoming::IsIncoming;
-use netwatch::packet_monitor::PacketMonitor;
+use netwatch::packet_monitor::{Attribution, Bucket, CaptureHandle, PacketMonitor, StopReason};
 use netwatch::port::PortMapper;
+use netwatch::record::Recorder;
 use netwatch::transfer::Transfer;
 
 mod app;
+mod export;
 
 use app::{App, AppEvent};
+use export::Format;
 
 fn main() {
-    let iface_name = match env::args().nth(1) {
-        Some(n) => n,
+    let mut iface_names = vec![];
+    let mut all_interfaces = false;
+    let mut record_path = None;
+    let mut filter = None;
+    let mut export = None;
+    let mut group = None;
+    let mut decapsulate = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record_path = args.next(),
+            "--filter" => filter = args.next(),
+            "--all" => all_interfaces = true,
+            "--export" => export = args.next(),
+            "--group" => group = args.next(),
+            "--decapsulate" => decapsulate = true,
+            _ => iface_names.push(arg),
+        }
+    }
+
+    if iface_names.is_empty() && !all_interfaces {
+        eprintln!("USAGE: netwatch [--record <FILE>] [--filter <EXPRESSION>] [--export <csv|json>] [--group <process|unit|container|user>] [--decapsulate] (--all | <NETWORK INTERFACE>...)");
+        for interface in datalink::interfaces() {
+            eprintln!("- {}", interface.name);
+        }
+        std::process::exit(1);
+    }
+
+    // Write machine-readable rows instead of showing the UI
+    let format = export.map(|name| match Format::from_name(&name) {
+        Some(format) => format,
         None => {
-            writeln!(io::stderr(), "USAGE: packetdump <NETWORK INTERFACE>").unwrap();
-            for interface in datalink::interfaces() {
-                writeln!(io::stderr(), "- {}", interface.name).unwrap();
-            }
+            eprintln!("Unknown export format: {}", name);
             std::process::exit(1);
         }
+    });
+
+    // What traffic is attributed to, in both the UI and exports
+    let grouping = match group {
+        Some(name) => match Grouping::ALL
+            .iter()
+            .find(|grouping| export::grouping_name(**grouping) == name)
+        {
+            Some(grouping) => *grouping,
+            None => {
+                eprintln!("Unknown grouping: {}", name);
+                std::process::exit(1);
+            }
+        },
+        None => Grouping::Process,
     };
 
-    // Find the network interface with the provided name
-    let interfaces = datalink::interfaces();
-    let interface = interfaces
-        .into_iter()
-        .filter(|iface: &NetworkInterface| iface.name == iface_name)
-        .next()
-        .unwrap();
+    // Find the network interfaces with the provided names (or every one that's up)
+    let interfaces: Vec<NetworkInterface> = if all_interfaces {
+        datalink::interfaces()
+            .into_iter()
+            .filter(|iface| iface.is_up())
+            .collect()
+    } else {
+        let available = datalink::interfaces();
+        iface_names
+            .iter()
+            .map(
+                |name| match available.iter().find(|iface| iface.name == *name) {
+                    Some(iface) => iface.clone(),
+                    None => {
+                        eprintln!("Unknown network interface: {}", name);
+                        std::process::exit(1);
+                    }
+                },
+            )
+            .collect()
+    };
 
-    // TODO: show all interfaces in UI
-    // TODO: handle terminations/allow quitting/etc
     // ---
 
     // NOTE: potentially create multiple connection tables so TCP/UDP packets don't fight for the lock?
@@ -52,18 +110,74 @@ fn main() {
     let connections = ConnectionTable::new();
     let connections = Arc::new(Mutex::new(connections));
 
-    let mut monitor = PacketMonitor::new(interface);
+    // The app (or export) refreshes its own port mapper, and publishes a copy of it after each
+    // refresh so the recorder can annotate packets with their processes without waiting for one.
+    let port_mapper = Arc::new(Mutex::new(Arc::new(PortMapper::new())));
+
+    let mut monitor = PacketMonitor::with_interfaces(interfaces.clone());
+
+    // ---
+    // NOTE: optionally only capture frames matching a tcpdump-style filter
+
+    if let Some(filter) = filter {
+        match Filter::new(&filter) {
+            Ok(filter) => monitor.set_filter(filter),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
     // ---
-    // NOTE: handle total incoming and outgoing
+    // NOTE: optionally count tunnelled traffic towards the flows inside the tunnels
 
-    let total_transfer = Transfer::new();
-    let total_transfer = Arc::new(Mutex::new(total_transfer));
+    monitor.set_decapsulate(decapsulate);
+
+    // ---
+    // NOTE: optionally record every frame (annotated with its processes) to a pcapng file
+
+    if let Some(record_path) = record_path {
+        let mut recorder = match Recorder::create(&record_path) {
+            Ok(recorder) => recorder,
+            Err(e) => {
+                eprintln!("Failed to create {}: {}", record_path, e);
+                std::process::exit(1);
+            }
+        };
+        let port_mapper_recorder = port_mapper.clone();
+        recorder.set_annotator(move |flow| {
+            let port_mapper = port_mapper_recorder.lock().unwrap().clone();
+            port_mapper
+                .get(flow.protocol, flow.local_addr, flow.local_port)
+                .map(|processes| {
+                    processes
+                        .iter()
+                        .map(|process| format!("pid={} comm={}", process.pid, process.stat.comm))
+                        .collect::<Vec<String>>()
+                        .join("; ")
+                })
+        });
+        monitor.set_recorder(recorder);
+    }
 
-    let total_transfer_ethernet = total_transfer.clone();
-    monitor.set_handler_ethernet_frame(move |iface, eth| {
-        let mut total_transfer = total_transfer_ethernet.lock().unwrap();
-        let size = eth.packet().len() as u64;
+    // ---
+    // NOTE: handle total incoming and outgoing per interface (the combined total is summed up
+    // when printing)
+
+    let total_transfers: HashMap<String, Transfer> = interfaces
+        .iter()
+        .map(|iface| (iface.name.clone(), Transfer::new()))
+        .collect();
+    let total_transfers = Arc::new(Mutex::new(total_transfers));
+
+    let total_transfers_ethernet = total_transfers.clone();
+    monitor.set_handler_ethernet_frame(move |iface, eth, len| {
+        let mut total_transfers = total_transfers_ethernet.lock().unwrap();
+        let total_transfer = total_transfers
+            .entry(iface.name.clone())
+            .or_default();
+        let size = len as u64;
         if eth.is_incoming(iface) {
             total_transfer.incr_incoming(size);
         } else {
@@ -72,152 +186,186 @@ fn main() {
     });
 
     // ---
-    // NOTE: handle per-process incoming and outgoing
-    // TODO: should we handle more than just TCP and UDP?
-
-    let connections_tcp = connections.clone();
-    monitor.set_handler_tcp_packet(move |iface, src_dest, tcp| {
-        let port = tcp.get_destination();
-        let size = tcp.packet().len() as u64;
-        // TODO: a nicer way to represent `src_dest`
-        if src_dest.1.is_incoming(iface) {
-            connections_tcp.lock().unwrap().incr_incoming(port, size);
-        } else {
-            connections_tcp.lock().unwrap().incr_outgoing(port, size);
+    // NOTE: handle per-process incoming and outgoing, whole frames are counted so that flows and
+    // buckets (traffic without a flow, e.g. ARP) add up to the interface totals
+
+    let buckets: HashMap<Bucket, Transfer> = HashMap::new();
+    let buckets = Arc::new(Mutex::new(buckets));
+
+    // VLAN totals are by interface, since the same ID can mean different networks on each
+    let vlan_transfers: HashMap<(String, u16), Transfer> = HashMap::new();
+    let vlan_transfers = Arc::new(Mutex::new(vlan_transfers));
+
+    let connections_attributed = connections.clone();
+    let buckets_attributed = buckets.clone();
+    let vlan_transfers_attributed = vlan_transfers.clone();
+    monitor.set_handler_attributed_frame(move |iface, eth, len, vlan, attribution| {
+        let size = len as u64;
+        if let Some(vlan) = vlan {
+            let mut vlan_transfers = vlan_transfers_attributed.lock().unwrap();
+            let transfer = vlan_transfers
+                .entry((iface.name.clone(), vlan))
+                .or_default();
+            if eth.is_incoming(iface) {
+                transfer.incr_incoming(size);
+            } else {
+                transfer.incr_outgoing(size);
+            }
         }
-    });
 
-    let connections_udp = connections.clone();
-    monitor.set_handler_udp_packet(move |iface, src_dest, udp| {
-        let port = udp.get_destination();
-        let size = udp.packet().len() as u64;
-        if src_dest.1.is_incoming(iface) {
-            connections_udp.lock().unwrap().incr_incoming(port, size);
-        } else {
-            connections_udp.lock().unwrap().incr_outgoing(port, size);
+        match attribution {
+            Attribution::Flow(flow, true) => connections_attributed
+                .lock()
+                .unwrap()
+                .incr_incoming(*flow, size),
+            Attribution::Flow(flow, false) => connections_attributed
+                .lock()
+                .unwrap()
+                .incr_outgoing(*flow, size),
+            Attribution::Bucket(bucket) => {
+                let mut buckets = buckets_attributed.lock().unwrap();
+                let transfer = buckets.entry(*bucket).or_default();
+                if eth.is_incoming(iface) {
+                    transfer.incr_incoming(size);
+                } else {
+                    transfer.incr_outgoing(size);
+                }
+            }
         }
     });
 
-    // ---
-    // NOTE: thread to periodically iterate connection table with port mapper
-    // and print connection information
-
-    let interval = 1_000;
-    let connections_thread = connections.clone();
-    let total_transfer_thread = total_transfer.clone();
-    thread::spawn(move || {
-        loop {
-            // NOTE: locking the connections here means we can't get packets in our handlers...
-            // TODO: combine transfers from the same process
-            // TODO: reset & calc over time interval
-            let mut port_mapper = PortMapper::new();
-            port_mapper.refresh();
-
-            let mut unknown = Transfer::new();
-
-            // Open the total transfer lock.
-            {
-                let total_transfer = &mut *total_transfer_thread.lock().unwrap();
-                let (incoming, outgoing) = total_transfer.stats(interval);
-                println!("Total:            {} {}", incoming, outgoing);
-                total_transfer.reset();
-            }
-
-            // Open the read lock on connections for as short a time as possible.
-            {
-                let connections = &mut *connections_thread.lock().unwrap();
-                for (port, transfer) in connections {
-                    println!("Port: [{:>6}] {}", port, transfer);
-                    if let Some(processes) = port_mapper.get(&port) {
-                        // Port's transfer...
-                        let (incoming, outgoing) = transfer.stats(interval);
-                        println!("Transfer:         {} {}", incoming, outgoing);
-
-                        // Port's processes...
-                        println!("\tAssociated processes: {}", processes.len());
-                        for process in processes {
-                            let name = process.cmdline().unwrap().join(" ");
-                            println!("\t\t{}", name = name);
-                        }
-                    } else {
-                        unknown.merge(&transfer);
-                    }
+    // Problems with the captured frames are shown in the status bar rather than printed
+    let capture_stats = monitor.stats();
 
-                    transfer.reset();
-                }
+    // ---
+    // NOTE: start capturing packets in the background
 
-                // Unknown...
-                let (incoming, outgoing) = unknown.stats(interval);
-                println!("Unknown:          {} {}", incoming, outgoing);
-            }
+    let capture = match monitor.start() {
+        Ok(capture) => capture,
+        Err(e) => {
+            eprintln!("Failed to start capture: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-            println!("");
-            thread::sleep(Duration::from_millis(interval));
+    // ---
+    // NOTE: optionally export what each process (or group) transferred instead of showing the UI
+
+    if let Some(format) = format {
+        let stdout = stdout();
+        if let Err(e) = export::run(
+            stdout.lock(),
+            format,
+            grouping,
+            Duration::from_millis(1_000),
+            &connections,
+            &port_mapper,
+            &capture,
+        ) {
+            eprintln!("Failed to export: {}", e);
         }
-    });
+        stop(capture);
+        return;
+    }
 
     // --- UI setup
 
     terminal::enable_raw_mode().unwrap();
 
     let mut stdout = stdout();
-    crossterm::execute!(stdout, EnterAlternateScreen).unwrap();
+    stdout.execute(EnterAlternateScreen).unwrap();
 
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend).unwrap();
     terminal.hide_cursor().unwrap();
 
     // Setup input handling
+    let tick_rate = Duration::from_millis(1_000);
     let (tx, rx) = mpsc::channel();
 
-    thread::spawn(move || {
+    // Stops once the UI has (so nothing is listening), or if reading the terminal fails, which
+    // the UI finds out about when it has no more events.
+    let input = thread::spawn(move || -> crossterm::Result<()> {
+        let mut last_tick = Instant::now();
         loop {
-            // poll for tick rate duration, if no events, sent tick event.
-            if event::poll(Duration::from_millis(interval)).unwrap() {
-                if let Event::Key(key) = event::read().unwrap() {
-                    tx.send(AppEvent::Input(key)).unwrap();
+            // poll until the next tick is due, and only send it once it is, so key presses don't
+            // make ticks (and so the rates) come early.
+            let timeout = tick_rate
+                .checked_sub(last_tick.elapsed())
+                .unwrap_or_default();
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if tx.send(AppEvent::Input(key)).is_err() {
+                        return Ok(());
+                    }
                 }
             }
 
-            tx.send(AppEvent::Tick).unwrap();
+            if last_tick.elapsed() >= tick_rate {
+                if tx.send(AppEvent::Tick).is_err() {
+                    return Ok(());
+                }
+                last_tick = Instant::now();
+            }
         }
     });
 
-    let mut app = App::new("Crossterm Demo");
+    let mut app = App::new(
+        "netwatch",
+        connections.clone(),
+        total_transfers.clone(),
+        vlan_transfers.clone(),
+        buckets.clone(),
+        capture_stats,
+        port_mapper.clone(),
+    );
+    app.set_grouping(grouping);
 
     terminal.clear().unwrap();
 
-    loop {
+    let result = loop {
         // Draw...
-        app.draw(&mut terminal).unwrap();
+        if let Err(e) = app.draw(&mut terminal) {
+            break Err(e.into());
+        }
 
         // Handle ...
-        match rx.recv().unwrap() {
-            AppEvent::Input(event) => match event.code {
-                KeyCode::Char('q') => {
-                    terminal::disable_raw_mode().unwrap();
-                    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen).unwrap();
-                    terminal.show_cursor().unwrap();
-                    break;
-                }
+        match rx.recv() {
+            Ok(AppEvent::Input(event)) => match event.code {
                 KeyCode::Char(c) => app.on_key(c),
                 KeyCode::Left => app.on_left(),
                 KeyCode::Up => app.on_up(),
                 KeyCode::Right => app.on_right(),
                 KeyCode::Down => app.on_down(),
+                KeyCode::Esc => app.on_escape(),
                 _ => {}
             },
-            AppEvent::Tick => {
+            Ok(AppEvent::Tick) => {
                 app.on_tick();
             }
+            // The input thread has stopped, because it couldn't read the terminal.
+            Err(_) => break input.join().unwrap_or(Ok(())),
         }
 
         if app.should_quit {
-            break;
+            break Ok(());
         }
+    };
+
+    // However the UI ended, put the terminal back the way it was before saying why.
+    let _ = terminal::disable_raw_mode();
+    let _ = terminal.backend_mut().execute(LeaveAlternateScreen);
+    let _ = terminal.show_cursor();
+    if let Err(e) = result {
+        eprintln!("Failed to run the UI: {}", e);
     }
 
-    // ---
-    // NOTE: this currently blocks, might be nice to have it spawn a thread instead.
-    monitor.start();
+    stop(capture);
+}
+
+// Stops capturing, and reports if the capture ended early.
+fn stop(capture: CaptureHandle) {
+    if let (_, StopReason::Error(e)) = capture.stop() {
+        eprintln!("Capture stopped: {}", e);
+    }
 }